@@ -37,8 +37,8 @@ pub struct ServerConfig {
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct CoreConfig {
     pub heartbeat_manager: HeartbeatManagerType,
-    pub instruct_manager: InstructManagerType,
-    pub manipulate_manager: ManipulateManagerType,
+    pub instruct_manager: InstructManagerConfig,
+    pub manipulate_manager: ManipulateManagerConfig,
     pub submodule_manager: SubmoduleManagerType,
     pub instruct_matcher: InstructMatcherConfig,
     pub instruct_encoder: InstructEncoderConfig,
@@ -57,6 +57,7 @@ pub enum HeartbeatManagerType {
 pub enum InstructManagerType {
     #[default]
     Simple,
+    Concurrent,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
@@ -82,6 +83,9 @@ pub enum InstructMatcherType {
     GrpcQdrant,
     #[default]
     InstantDistance,
+    ExactCosine,
+    Rule,
+    Hybrid,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
@@ -95,6 +99,18 @@ pub enum OperationRecorderType {
     #[default]
     Log,
     Sqlite,
+    Memory,
+    JsonLines,
+    Composite,
+}
+
+/// 记录器写入失败时的处理方式，`Fail`会使对应管理线程退出
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+pub enum RecorderFailurePolicy {
+    Ignore,
+    Warn,
+    #[default]
+    Fail,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -104,10 +120,94 @@ pub struct InstructEncoderConfig {
     pub config_map: HashMap<String, String>,
 }
 
+/// 兼容旧版本配置中只写类型名称的写法，如`instruct_manager = "Simple"`
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(from = "InstructManagerConfigCompat")]
+pub struct InstructManagerConfig {
+    pub instruct_manager_type: InstructManagerType,
+    #[serde(default)]
+    pub config_map: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InstructManagerConfigCompat {
+    Type(InstructManagerType),
+    Config {
+        instruct_manager_type: InstructManagerType,
+        #[serde(default)]
+        config_map: HashMap<String, String>,
+    },
+}
+
+impl From<InstructManagerConfigCompat> for InstructManagerConfig {
+    fn from(compat: InstructManagerConfigCompat) -> Self {
+        match compat {
+            InstructManagerConfigCompat::Type(instruct_manager_type) => InstructManagerConfig {
+                instruct_manager_type,
+                config_map: HashMap::new(),
+            },
+            InstructManagerConfigCompat::Config {
+                instruct_manager_type,
+                config_map,
+            } => InstructManagerConfig {
+                instruct_manager_type,
+                config_map,
+            },
+        }
+    }
+}
+
+/// 兼容旧版本配置中只写类型名称的写法，如`manipulate_manager = "Simple"`
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(from = "ManipulateManagerConfigCompat")]
+pub struct ManipulateManagerConfig {
+    pub manipulate_manager_type: ManipulateManagerType,
+    #[serde(default)]
+    pub config_map: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManipulateManagerConfigCompat {
+    Type(ManipulateManagerType),
+    Config {
+        manipulate_manager_type: ManipulateManagerType,
+        #[serde(default)]
+        config_map: HashMap<String, String>,
+    },
+}
+
+impl From<ManipulateManagerConfigCompat> for ManipulateManagerConfig {
+    fn from(compat: ManipulateManagerConfigCompat) -> Self {
+        match compat {
+            ManipulateManagerConfigCompat::Type(manipulate_manager_type) => {
+                ManipulateManagerConfig {
+                    manipulate_manager_type,
+                    config_map: HashMap::new(),
+                }
+            }
+            ManipulateManagerConfigCompat::Config {
+                manipulate_manager_type,
+                config_map,
+            } => ManipulateManagerConfig {
+                manipulate_manager_type,
+                config_map,
+            },
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct InstructMatcherConfig {
     pub instruct_matcher_type: InstructMatcherType,
     pub config_map: HashMap<String, String>,
+    /// 子模块名称到匹配阈值，覆盖全局阈值
+    #[serde(default)]
+    pub submodule_threshold: HashMap<String, f32>,
+    /// 子模块名称到默认指令及其匹配阈值，优先级最高
+    #[serde(default)]
+    pub instruct_threshold: HashMap<String, HashMap<String, f32>>,
 }
 
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
@@ -120,6 +220,31 @@ pub struct SubmoduleStoreConfig {
 pub struct OperationRecorderConfig {
     pub operation_recorder_type: OperationRecorderType,
     pub config_map: HashMap<String, String>,
+    #[serde(default)]
+    pub failure_policy: RecorderFailurePolicy,
+    /// 仅`Composite`类型使用，每个记录器都会收到全部记录
+    #[serde(default)]
+    pub recorders: Vec<OperationRecorderConfig>,
+    /// 删除早于该天数的记录，0表示不限制
+    #[serde(default)]
+    pub retention_days: u64,
+    /// 每类记录最多保留的条数，0表示不限制
+    #[serde(default)]
+    pub retention_max_records: usize,
+    /// 写入前对指令文本依次应用的脱敏规则
+    #[serde(default)]
+    pub redaction_rules: Vec<RedactionRule>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum RedactionRule {
+    /// 将正则匹配到的内容替换为`replacement`
+    Mask {
+        pattern: String,
+        replacement: String,
+    },
+    /// 将整段文本替换为其sha256摘要
+    Hash,
 }
 
 impl Default for NihilityTerminalConfig {
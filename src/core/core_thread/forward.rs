@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error, Result};
+use nihility_common::{ModuleOperate, NihilityClient, OperateType};
+use tokio::time::error::Elapsed;
+use tokio::time::{sleep, timeout};
+use tonic::{Code, Status};
+use tracing::{info, warn};
+
+use crate::core::submodule_store::SubmoduleHealth;
+use crate::core::SubmoduleStoreImpl;
+use crate::entity::operation_record::InboundEntity;
+use crate::MODULE_OPERATE_SENDER;
+
+/// 单次转发的超时时间，为0时不限制
+pub const FORWARD_TIMEOUT_MS_FIELD: &str = "forward_timeout_ms";
+/// 转发出现临时错误时的最大重试次数
+pub const FORWARD_RETRIES_FIELD: &str = "forward_retries";
+/// 首次重试前的等待时间，之后每次重试翻倍
+pub const FORWARD_BACKOFF_MS_FIELD: &str = "forward_backoff_ms";
+const DEFAULT_FORWARD_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_FORWARD_RETRIES: u32 = 0;
+const DEFAULT_FORWARD_BACKOFF_MS: u64 = 200;
+
+/// 转发到子模块的超时与重试策略
+#[derive(Clone, Debug)]
+pub struct ForwardPolicy {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl ForwardPolicy {
+    pub fn new(config_map: &HashMap<String, String>) -> Result<Self> {
+        let timeout = match config_map.get(FORWARD_TIMEOUT_MS_FIELD) {
+            None => Some(Duration::from_millis(DEFAULT_FORWARD_TIMEOUT_MS)),
+            Some(forward_timeout) => match forward_timeout.parse::<u64>()? {
+                0 => None,
+                forward_timeout => Some(Duration::from_millis(forward_timeout)),
+            },
+        };
+        let retries = match config_map.get(FORWARD_RETRIES_FIELD) {
+            None => DEFAULT_FORWARD_RETRIES,
+            Some(retries) => retries.parse::<u32>()?,
+        };
+        let backoff = match config_map.get(FORWARD_BACKOFF_MS_FIELD) {
+            None => Duration::from_millis(DEFAULT_FORWARD_BACKOFF_MS),
+            Some(backoff) => Duration::from_millis(backoff.parse::<u64>()?),
+        };
+        Ok(ForwardPolicy {
+            timeout,
+            retries,
+            backoff,
+        })
+    }
+}
+
+pub enum ForwardResult<T> {
+    /// 子模块未注册
+    NotRegistered,
+    /// 子模块熔断中，未进行转发
+    CircuitOpen,
+    /// 已转发，包含最后一次转发的结果
+    Completed(Result<T>),
+}
+
+/// 超时、连接错误与表示服务暂时不可用的gRPC状态视为临时错误，可以重试，
+/// 其余错误重试也不会成功
+fn is_transient(e: &Error) -> bool {
+    if e.downcast_ref::<Elapsed>().is_some()
+        || e.downcast_ref::<tonic::transport::Error>().is_some()
+    {
+        return true;
+    }
+    match e.downcast_ref::<Status>() {
+        None => false,
+        Some(status) => matches!(
+            status.code(),
+            Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+        ),
+    }
+}
+
+/// 按策略转发到指定子模块，转发期间不持有存储锁，
+/// 临时错误按退避时间重试，重试后仍失败的转发计入子模块熔断状态，连续失败过多时通知下线该子模块
+pub async fn forward_to_submodule<T, F, Fut>(
+    submodule_store: &SubmoduleStoreImpl,
+    submodule_name: &String,
+    forward_policy: &ForwardPolicy,
+    forward: F,
+) -> Result<ForwardResult<T>>
+where
+    F: Fn(Arc<Box<dyn NihilityClient + Send + Sync>>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let client = {
+        let mut submodule_store = submodule_store.lock().await;
+        let client = match submodule_store.get(submodule_name).await? {
+            None => return Ok(ForwardResult::NotRegistered),
+            Some(module) => module.client.clone(),
+        };
+        if !submodule_store.is_available(submodule_name).await? {
+            warn!("Submodule {:?} Circuit Open, Skip Forward", submodule_name);
+            return Ok(ForwardResult::CircuitOpen);
+        }
+        client
+    };
+    let mut backoff = forward_policy.backoff;
+    let mut attempt = 0;
+    let forward_result = loop {
+        let forward_result = match forward_policy.timeout {
+            None => forward(client.clone()).await,
+            Some(forward_timeout) => {
+                match timeout(forward_timeout, forward(client.clone())).await {
+                    Ok(forward_result) => forward_result,
+                    Err(elapsed) => Err(Error::new(elapsed).context(format!(
+                        "Forward Timeout After {}ms",
+                        forward_timeout.as_millis()
+                    ))),
+                }
+            }
+        };
+        match forward_result {
+            Err(e) if attempt < forward_policy.retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "Forward To Submodule {:?} Error: {}, Retry {}/{} After {}ms",
+                    submodule_name,
+                    e,
+                    attempt,
+                    forward_policy.retries,
+                    backoff.as_millis()
+                );
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+            forward_result => break forward_result,
+        }
+    };
+    let submodule_health = submodule_store
+        .lock()
+        .await
+        .record_forward_result(submodule_name, forward_result.is_ok())
+        .await?;
+    if let SubmoduleHealth::Offline = submodule_health {
+        info!(
+            "Submodule {:?} Forward Failed Too Many Times, Offline It",
+            submodule_name
+        );
+        MODULE_OPERATE_SENDER
+            .get()
+            .and_then(|sender| sender.upgrade())
+            .ok_or(anyhow!("Module Operate Sender Not Init"))?
+            .send(InboundEntity::live(ModuleOperate {
+                name: submodule_name.to_string(),
+                operate_type: OperateType::Offline,
+                ..Default::default()
+            }))?;
+    }
+    Ok(ForwardResult::Completed(forward_result))
+}
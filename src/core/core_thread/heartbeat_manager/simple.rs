@@ -6,6 +6,7 @@ use tracing::{debug, info};
 
 use crate::core::core_thread::heartbeat_manager::HEARTBEAT_TIME;
 use crate::core::SubmoduleStoreImpl;
+use crate::entity::operation_record::InboundEntity;
 use crate::MODULE_OPERATE_SENDER;
 
 pub async fn simple_heartbeat_manager_thread(submodule_store: SubmoduleStoreImpl) -> Result<()> {
@@ -29,7 +30,7 @@ pub async fn simple_heartbeat_manager_thread(submodule_store: SubmoduleStoreImpl
                 .unwrap()
                 .upgrade()
                 .unwrap()
-                .send(operate)?;
+                .send(InboundEntity::live(operate))?;
         }
     }
 }
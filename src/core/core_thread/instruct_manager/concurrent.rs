@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use nihility_common::InstructEntity;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender, UnboundedReceiver};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tokio::{select, spawn};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::config::InstructManagerConfig;
+use crate::core::core_thread::forward::ForwardPolicy;
+use crate::core::core_thread::instruct_manager::{
+    deliver_instruct, search_instruct, FallbackRoute,
+};
+use crate::core::instruct_matcher::MatchResult;
+use crate::core::{
+    InstructEncoderImpl, InstructMatcherImpl, OperationRecorderImpl, SubmoduleStoreImpl,
+};
+use crate::entity::operation_record::{InboundEntity, OperationOutcome, RecordKind};
+use crate::CANCELLATION_TOKEN;
+
+/// 同时进行检索或转发的最大任务数
+pub const MAX_WORKERS_FIELD: &str = "max_workers";
+/// 每个子模块等待转发的最大指令数，队列已满时丢弃新指令并记录处理结果
+pub const FORWARD_QUEUE_SIZE_FIELD: &str = "forward_queue_size";
+const DEFAULT_MAX_WORKERS: usize = 8;
+const DEFAULT_FORWARD_QUEUE_SIZE: usize = 64;
+/// 转发任务空闲超过该时间后由分发任务回收，下线的子模块不再占用任务，收到新指令时重新创建
+const FORWARD_WORKER_IDLE_TIME: Duration = Duration::from_secs(60);
+
+/// 检索任务及其记录id与是否回放，任务失败时仍可记录处理结果
+type SearchTask = (String, bool, JoinHandle<Result<ForwardJob>>);
+
+struct ForwardJob {
+    instruct: InboundEntity<InstructEntity>,
+    match_result: Option<MatchResult>,
+    outcome: OperationOutcome,
+}
+
+#[derive(Clone)]
+struct ForwardContext {
+    submodule_store: SubmoduleStoreImpl,
+    operation_recorder: OperationRecorderImpl,
+    fallback_route: FallbackRoute,
+    semaphore: Arc<Semaphore>,
+    forward_policy: ForwardPolicy,
+    forward_queue_size: usize,
+}
+
+/// 子模块对应的转发任务及其队列
+struct ForwardWorker {
+    forward_sender: Sender<ForwardJob>,
+    handle: JoinHandle<()>,
+    last_dispatch: Instant,
+}
+
+impl ForwardContext {
+    /// 同一子模块被回收的转发任务尚未退出时，新任务等待其转发完剩余指令后再开始
+    fn spawn_forward_worker(
+        &self,
+        target_submodule: &str,
+        retired_handle: Option<JoinHandle<()>>,
+    ) -> ForwardWorker {
+        debug!("Start Forward Worker For Submodule {:?}", target_submodule);
+        let (forward_sender, forward_receiver) = channel::<ForwardJob>(self.forward_queue_size);
+        ForwardWorker {
+            forward_sender,
+            handle: spawn_cancel_on_error(
+                "Instruct Forward Worker",
+                forward_worker(forward_receiver, retired_handle, self.clone()),
+            ),
+            last_dispatch: Instant::now(),
+        }
+    }
+
+    /// 记录未能转发的指令的处理结果，回放的指令不记录，记录失败只输出日志
+    async fn record_dropped(&self, mut outcome: OperationOutcome, error: String, replayed: bool) {
+        warn!("Drop Instruct {:?}: {}", &outcome.record_id, &error);
+        if replayed {
+            return;
+        }
+        outcome.error = Some(error);
+        if let Err(e) = self.operation_recorder.recorder_outcome(&outcome).await {
+            error!("Record Dropped Instruct Outcome Error: {}", e);
+        }
+    }
+}
+
+/// 任务出错时与管理线程出错一样取消整个程序
+fn spawn_cancel_on_error(
+    task_name: &'static str,
+    task: impl Future<Output = Result<()>> + Send + 'static,
+) -> JoinHandle<()> {
+    spawn(async move {
+        if let Err(e) = task.await {
+            error!("{} Error: {}", task_name, e);
+            CANCELLATION_TOKEN.cancel();
+        }
+    })
+}
+
+/// 指令的编码与检索并发执行，检索完成后按接收顺序分发到每个目标子模块各自的转发任务，
+/// 保证同一子模块收到的指令顺序与接收顺序一致，慢速子模块不会阻塞其他子模块
+pub async fn concurrent_instruct_manager_thread(
+    instruct_manager_config: InstructManagerConfig,
+    instruct_encoder: InstructEncoderImpl,
+    instruct_matcher: InstructMatcherImpl,
+    submodule_store: SubmoduleStoreImpl,
+    operation_recorder: OperationRecorderImpl,
+    mut instruct_receiver: UnboundedReceiver<InboundEntity<InstructEntity>>,
+) -> Result<()> {
+    let config_map = &instruct_manager_config.config_map;
+    let max_workers = match config_map.get(MAX_WORKERS_FIELD) {
+        None => DEFAULT_MAX_WORKERS,
+        Some(max_workers) => max_workers.parse::<usize>()?.max(1),
+    };
+    let forward_queue_size = match config_map.get(FORWARD_QUEUE_SIZE_FIELD) {
+        None => DEFAULT_FORWARD_QUEUE_SIZE,
+        Some(forward_queue_size) => forward_queue_size.parse::<usize>()?.max(1),
+    };
+    let forward_policy = ForwardPolicy::new(config_map)?;
+    info!(
+        "Concurrent Instruct Manager Thread Start, Max Workers: {}, Forward Queue Size: {}, Forward Policy: {:?}",
+        max_workers, forward_queue_size, forward_policy
+    );
+    let semaphore = Arc::new(Semaphore::new(max_workers));
+    let forward_context = ForwardContext {
+        submodule_store,
+        operation_recorder: operation_recorder.clone(),
+        fallback_route: FallbackRoute::new(&instruct_manager_config),
+        semaphore: semaphore.clone(),
+        forward_policy,
+        forward_queue_size,
+    };
+    let (search_sender, search_receiver) = channel::<SearchTask>(max_workers);
+    let dispatcher = spawn_cancel_on_error(
+        "Instruct Dispatcher",
+        dispatch_forward_jobs(search_receiver, forward_context),
+    );
+
+    while let Some(instruct) = instruct_receiver.recv().await {
+        let record_id = Uuid::new_v4().to_string();
+        info!(
+            "Get Instruct {:?}, Receive Manipulate Submodule: {:?}",
+            &record_id, &instruct.entity.info.receive_manipulate_submodule
+        );
+        // 没有空闲的任务时在此等待，避免检索任务无限堆积
+        let permit = semaphore.clone().acquire_owned().await?;
+        let instruct_encoder = instruct_encoder.clone();
+        let instruct_matcher = instruct_matcher.clone();
+        let operation_recorder = operation_recorder.clone();
+        let task_record_id = record_id.to_string();
+        let replayed = instruct.replayed;
+        let search_handle = spawn(async move {
+            let _permit = permit;
+            // 回放的指令已有记录，不再重复记录
+            if !instruct.replayed {
+                operation_recorder
+                    .recorder_instruct(&record_id, &instruct.entity)
+                    .await?;
+            }
+            let mut outcome = OperationOutcome::new(&record_id, RecordKind::Instruct);
+            let match_result = search_instruct(
+                &instruct_encoder,
+                &instruct_matcher,
+                &instruct.entity,
+                &mut outcome,
+            )
+            .await;
+            Ok(ForwardJob {
+                instruct,
+                match_result,
+                outcome,
+            })
+        });
+        // 分发落后时在此等待，避免已完成检索的任务无限堆积
+        search_sender
+            .send((task_record_id, replayed, search_handle))
+            .await?;
+    }
+    drop(search_sender);
+    dispatcher.await?;
+    Ok(())
+}
+
+/// 按接收顺序等待检索结果，分发到目标子模块对应的转发任务，
+/// 检索任务失败或目标子模块队列已满时只记录该指令的处理结果，不影响其他指令，
+/// 定期回收空闲的转发任务
+async fn dispatch_forward_jobs(
+    mut search_receiver: Receiver<SearchTask>,
+    forward_context: ForwardContext,
+) -> Result<()> {
+    let mut forward_workers = HashMap::<String, ForwardWorker>::new();
+    let mut retired_handles = HashMap::<String, JoinHandle<()>>::new();
+    let mut retire_interval = interval(FORWARD_WORKER_IDLE_TIME);
+    loop {
+        let (record_id, replayed, search_handle) = select! {
+            search_task = search_receiver.recv() => match search_task {
+                Some(search_task) => search_task,
+                None => break,
+            },
+            _ = retire_interval.tick() => {
+                retire_idle_workers(&mut forward_workers, &mut retired_handles).await?;
+                continue;
+            },
+        };
+        let forward_job = match search_handle.await {
+            Ok(Ok(forward_job)) => forward_job,
+            Ok(Err(e)) => {
+                forward_context
+                    .record_dropped(
+                        OperationOutcome::new(&record_id, RecordKind::Instruct),
+                        format!("Search Instruct Error: {}", e),
+                        replayed,
+                    )
+                    .await;
+                continue;
+            }
+            Err(e) => {
+                forward_context
+                    .record_dropped(
+                        OperationOutcome::new(&record_id, RecordKind::Instruct),
+                        format!("Search Instruct Task Error: {}", e),
+                        replayed,
+                    )
+                    .await;
+                continue;
+            }
+        };
+        // 未匹配的指令按兜底子模块分组，没有兜底子模块时统一使用空名称
+        let target_submodule = match &forward_job.match_result {
+            Some(match_result) => match_result.submodule_id.to_string(),
+            None => forward_context
+                .fallback_route
+                .submodule
+                .clone()
+                .unwrap_or_default(),
+        };
+        let forward_worker = forward_workers
+            .entry(target_submodule.to_string())
+            .or_insert_with(|| {
+                forward_context.spawn_forward_worker(
+                    &target_submodule,
+                    retired_handles.remove(&target_submodule),
+                )
+            });
+        forward_worker.last_dispatch = Instant::now();
+        let (forward_job, error) = match forward_worker.forward_sender.try_send(forward_job) {
+            Ok(()) => continue,
+            Err(TrySendError::Full(forward_job)) => (
+                forward_job,
+                format!("Forward Queue Of Submodule {:?} Full", target_submodule),
+            ),
+            Err(TrySendError::Closed(forward_job)) => (
+                forward_job,
+                format!("Forward Worker Of Submodule {:?} Exited", target_submodule),
+            ),
+        };
+        forward_context
+            .record_dropped(forward_job.outcome, error, forward_job.instruct.replayed)
+            .await;
+    }
+    for (_, forward_worker) in forward_workers {
+        drop(forward_worker.forward_sender);
+        forward_worker.handle.await?;
+    }
+    for (_, retired_handle) in retired_handles {
+        retired_handle.await?;
+    }
+    Ok(())
+}
+
+/// 关闭空闲超时的转发任务的队列，任务转发完剩余指令后退出，并等待已退出的任务
+async fn retire_idle_workers(
+    forward_workers: &mut HashMap<String, ForwardWorker>,
+    retired_handles: &mut HashMap<String, JoinHandle<()>>,
+) -> Result<()> {
+    let idle_submodules = forward_workers
+        .iter()
+        .filter(|(_, forward_worker)| {
+            forward_worker.last_dispatch.elapsed() >= FORWARD_WORKER_IDLE_TIME
+        })
+        .map(|(target_submodule, _)| target_submodule.to_string())
+        .collect::<Vec<String>>();
+    for target_submodule in idle_submodules {
+        if let Some(forward_worker) = forward_workers.remove(&target_submodule) {
+            debug!("Retire Forward Worker For Submodule {:?}", target_submodule);
+            retired_handles.insert(target_submodule, forward_worker.handle);
+        }
+    }
+    let finished_submodules = retired_handles
+        .iter()
+        .filter(|(_, retired_handle)| retired_handle.is_finished())
+        .map(|(target_submodule, _)| target_submodule.to_string())
+        .collect::<Vec<String>>();
+    for target_submodule in finished_submodules {
+        if let Some(retired_handle) = retired_handles.remove(&target_submodule) {
+            retired_handle.await?;
+        }
+    }
+    Ok(())
+}
+
+/// 等待同一子模块之前的转发任务退出后，按顺序转发该子模块的指令，队列关闭后退出
+async fn forward_worker(
+    mut forward_receiver: Receiver<ForwardJob>,
+    retired_handle: Option<JoinHandle<()>>,
+    forward_context: ForwardContext,
+) -> Result<()> {
+    if let Some(retired_handle) = retired_handle {
+        retired_handle.await?;
+    }
+    while let Some(forward_job) = forward_receiver.recv().await {
+        forward_instruct_job(&forward_context, forward_job).await?;
+    }
+    Ok(())
+}
+
+async fn forward_instruct_job(
+    forward_context: &ForwardContext,
+    mut forward_job: ForwardJob,
+) -> Result<()> {
+    let _permit = forward_context.semaphore.clone().acquire_owned().await?;
+    let replayed = forward_job.instruct.replayed;
+    deliver_instruct(
+        &forward_context.submodule_store,
+        &forward_context.fallback_route,
+        forward_job.match_result,
+        forward_job.instruct,
+        &mut forward_job.outcome,
+        &forward_context.forward_policy,
+    )
+    .await?;
+    if replayed {
+        return Ok(());
+    }
+    forward_context
+        .operation_recorder
+        .recorder_outcome(&forward_job.outcome)
+        .await
+}
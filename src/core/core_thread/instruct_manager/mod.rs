@@ -1,30 +1,74 @@
-use anyhow::Result;
-use nihility_common::InstructEntity;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use nihility_common::InstructData::Text;
+use nihility_common::{
+    InstructEntity, ManipulateData, ManipulateEntity, ManipulateType, ResponseCode,
+};
+use tokio::runtime::Handle;
 use tokio::spawn;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::error;
+use tokio::task::spawn_blocking;
+use tracing::{debug, error, info, warn};
 
+pub use concurrent::concurrent_instruct_manager_thread;
 pub use simple::simple_instruct_manager_thread;
 
+use crate::config::InstructManagerConfig;
+use crate::core::core_thread::forward::{forward_to_submodule, ForwardPolicy, ForwardResult};
+use crate::core::instruct_matcher::MatchResult;
 use crate::core::{
     InstructEncoderImpl, InstructManagerFn, InstructMatcherImpl, OperationRecorderImpl,
     SubmoduleStoreImpl,
 };
-use crate::{CANCELLATION_TOKEN, CLOSE_SENDER};
+use crate::entity::operation_record::{InboundEntity, OperationOutcome};
+use crate::{CANCELLATION_TOKEN, CLOSE_SENDER, MANIPULATE_SENDER};
 
+mod concurrent;
 mod simple;
 
+/// 未匹配的指令转发到该子模块
+pub const FALLBACK_SUBMODULE_FIELD: &str = "fallback_submodule";
+/// 未匹配且没有可用的兜底子模块时，将该文本回复给指令来源模块
+pub const FALLBACK_REPLY_FIELD: &str = "fallback_reply";
+/// 每次检索的候选数量，最佳匹配之外的候选仅用于记录近似匹配
+const SEARCH_TOP_K: usize = 3;
+
+/// 未匹配指令的兜底路由
+#[derive(Clone, Default, Debug)]
+pub struct FallbackRoute {
+    pub submodule: Option<String>,
+    pub reply: Option<String>,
+}
+
+impl FallbackRoute {
+    pub fn new(instruct_manager_config: &InstructManagerConfig) -> Self {
+        FallbackRoute {
+            submodule: instruct_manager_config
+                .config_map
+                .get(FALLBACK_SUBMODULE_FIELD)
+                .cloned(),
+            reply: instruct_manager_config
+                .config_map
+                .get(FALLBACK_REPLY_FIELD)
+                .cloned(),
+        }
+    }
+}
+
 pub fn instruct_manager_thread(
     instruct_manager_fn: Box<InstructManagerFn>,
+    instruct_manager_config: InstructManagerConfig,
     instruct_encoder: InstructEncoderImpl,
     instruct_matcher: InstructMatcherImpl,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    instruct_receiver: UnboundedReceiver<InstructEntity>,
+    instruct_receiver: UnboundedReceiver<InboundEntity<InstructEntity>>,
 ) -> Result<()> {
     let close_sender = CLOSE_SENDER.get().unwrap().upgrade().unwrap();
     spawn(async move {
         if let Err(e) = instruct_manager_fn(
+            instruct_manager_config,
             instruct_encoder,
             instruct_matcher,
             submodule_store,
@@ -43,3 +87,219 @@ pub fn instruct_manager_thread(
     });
     Ok(())
 }
+
+/// 模型推理是同步计算，放到阻塞线程池中执行，避免并发检索时占满异步工作线程
+async fn encode_blocking(instruct_encoder: &InstructEncoderImpl, text: &str) -> Result<Vec<f32>> {
+    let instruct_encoder = instruct_encoder.clone();
+    let text = text.to_string();
+    spawn_blocking(move || Handle::current().block_on(instruct_encoder.encode(&text))).await?
+}
+
+/// 匹配器不使用编码时跳过编码，避免编码失败影响规则匹配
+async fn encode_instruct(
+    instruct_encoder: &InstructEncoderImpl,
+    instruct_matcher: &InstructMatcherImpl,
+    text: &str,
+) -> Result<Vec<f32>> {
+    if !instruct_matcher.lock().await.requires_encode() {
+        return Ok(Vec::new());
+    }
+    encode_blocking(instruct_encoder, text).await
+}
+
+/// 编码并检索指令，达到匹配阈值时返回最佳匹配，匹配结果或错误记录到`outcome`中
+pub async fn search_instruct(
+    instruct_encoder: &InstructEncoderImpl,
+    instruct_matcher: &InstructMatcherImpl,
+    instruct: &InstructEntity,
+    outcome: &mut OperationOutcome,
+) -> Option<MatchResult> {
+    let search_result = match &instruct.instruct {
+        Text(text) => match encode_instruct(instruct_encoder, instruct_matcher, text).await {
+            Ok(encoded_instruct) => {
+                let instruct_matcher = instruct_matcher.lock().await;
+                instruct_matcher
+                    .search_top_k(text, encoded_instruct, SEARCH_TOP_K)
+                    .await
+                    .map(|match_results| {
+                        for candidate in match_results.iter().skip(1) {
+                            debug!(
+                                "Near Miss Candidate {:?} Of Submodule {:?}, Score: {}",
+                                &candidate.instruct, &candidate.submodule_id, candidate.score
+                            );
+                        }
+                        match match_results.into_iter().next() {
+                            Some(best) if instruct_matcher.is_match(&best) => Some(best),
+                            Some(best) => {
+                                info!(
+                                    "Best Candidate {:?} Of Submodule {:?} Below Threshold, Score: {}",
+                                    &best.instruct, &best.submodule_id, best.score
+                                );
+                                None
+                            }
+                            None => None,
+                        }
+                    })
+            }
+            Err(e) => Err(e),
+        },
+    };
+    match search_result {
+        Ok(Some(match_result)) => {
+            debug!(
+                "Instruct Match Point {:?} Of Submodule {:?}, Score: {}",
+                &match_result.uuid, &match_result.submodule_id, match_result.score
+            );
+            outcome.matched_submodule = Some(match_result.submodule_id.to_string());
+            outcome.matched_instruct = Some(match_result.instruct.to_string());
+            outcome.score = Some(match_result.score);
+            Some(match_result)
+        }
+        Ok(None) => {
+            warn!("No Submodule Matched This Instruct");
+            outcome.error = Some("No Submodule Matched".to_string());
+            None
+        }
+        Err(e) => {
+            warn!("Match Instruct Handler Error: {}", e);
+            outcome.error = Some(e.to_string());
+            None
+        }
+    }
+}
+
+/// 已匹配的指令转发到匹配的子模块，未匹配或匹配的子模块不可用时交给兜底路由，
+/// 回放的指令产生的兜底回复同样标记为回放
+pub async fn deliver_instruct(
+    submodule_store: &SubmoduleStoreImpl,
+    fallback_route: &FallbackRoute,
+    match_result: Option<MatchResult>,
+    instruct: InboundEntity<InstructEntity>,
+    outcome: &mut OperationOutcome,
+    forward_policy: &ForwardPolicy,
+) -> Result<()> {
+    if let Some(match_result) = match_result {
+        if forward_instruct(
+            submodule_store,
+            &match_result.submodule_id,
+            instruct.entity.clone(),
+            outcome,
+            forward_policy,
+        )
+        .await?
+        {
+            return Ok(());
+        }
+        warn!(
+            "Matched Submodule {:?} Unavailable, Fallback Instruct",
+            &match_result.submodule_id
+        );
+    }
+    fallback_instruct(
+        submodule_store,
+        fallback_route,
+        instruct,
+        outcome,
+        forward_policy,
+    )
+    .await
+}
+
+/// 将指令转发到指定子模块，子模块未注册或熔断中时返回false，转发期间不持有存储锁
+async fn forward_instruct(
+    submodule_store: &SubmoduleStoreImpl,
+    submodule_name: &String,
+    instruct: InstructEntity,
+    outcome: &mut OperationOutcome,
+    forward_policy: &ForwardPolicy,
+) -> Result<bool> {
+    let forward_start = Instant::now();
+    let forward_result =
+        match forward_to_submodule(submodule_store, submodule_name, forward_policy, |client| {
+            let instruct = instruct.clone();
+            async move { client.text_instruct(instruct).await }
+        })
+        .await?
+        {
+            ForwardResult::NotRegistered => {
+                outcome.error = Some(format!("Submodule {:?} Not Registered", submodule_name));
+                return Ok(false);
+            }
+            ForwardResult::CircuitOpen => {
+                outcome.error = Some(format!("Submodule {:?} Circuit Open", submodule_name));
+                return Ok(false);
+            }
+            ForwardResult::Completed(forward_result) => forward_result,
+        };
+    outcome.forward_latency = Some(forward_start.elapsed().as_millis() as u64);
+    match forward_result {
+        Ok(resp) => {
+            outcome.response_code = Some(format!("{:?}", resp.code()));
+            match resp.code() {
+                ResponseCode::Success => debug!("Forward Instruct Success"),
+                other_resp_code => {
+                    error!("Forward Instruct Fail, Resp Code: {:?}", other_resp_code)
+                }
+            }
+        }
+        Err(e) => {
+            error!("Forward Instruct Error: {}", e);
+            outcome.error = Some(e.to_string());
+        }
+    }
+    Ok(true)
+}
+
+/// 未匹配的指令优先转发到兜底子模块，兜底子模块未注册或熔断中时回复指令来源模块
+async fn fallback_instruct(
+    submodule_store: &SubmoduleStoreImpl,
+    fallback_route: &FallbackRoute,
+    instruct: InboundEntity<InstructEntity>,
+    outcome: &mut OperationOutcome,
+    forward_policy: &ForwardPolicy,
+) -> Result<()> {
+    if let Some(fallback_submodule) = &fallback_route.submodule {
+        info!("Fallback Instruct To Submodule {:?}", fallback_submodule);
+        outcome.matched_submodule = Some(fallback_submodule.to_string());
+        outcome.error = None;
+        if forward_instruct(
+            submodule_store,
+            fallback_submodule,
+            instruct.entity.clone(),
+            outcome,
+            forward_policy,
+        )
+        .await?
+        {
+            return Ok(());
+        }
+        warn!("Fallback Submodule {:?} Unavailable", fallback_submodule);
+    }
+    if let Some(fallback_reply) = &fallback_route.reply {
+        if instruct.entity.info.receive_manipulate_submodule.is_empty() {
+            warn!("Unmatched Instruct Has No Receive Manipulate Submodule, Skip Reply");
+            return Ok(());
+        }
+        let mut manipulate = ManipulateEntity::default();
+        manipulate.info.manipulate_type = ManipulateType::TextDisplayType;
+        manipulate.info.use_module_name = instruct
+            .entity
+            .info
+            .receive_manipulate_submodule
+            .to_string();
+        manipulate.manipulate = ManipulateData::Text(fallback_reply.to_string());
+        info!(
+            "Reply Unmatched Instruct To Submodule {:?}",
+            &manipulate.info.use_module_name
+        );
+        MANIPULATE_SENDER
+            .get()
+            .and_then(|sender| sender.upgrade())
+            .ok_or(anyhow!("Manipulate Sender Not Init"))?
+            .send(InboundEntity {
+                entity: manipulate,
+                replayed: instruct.replayed,
+            })?;
+    }
+    Ok(())
+}
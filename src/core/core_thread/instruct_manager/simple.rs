@@ -1,49 +1,62 @@
 use anyhow::Result;
-use nihility_common::InstructData::Text;
-use nihility_common::{InstructEntity, ResponseCode};
+use nihility_common::InstructEntity;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::{debug, error, info, warn};
+use tracing::info;
+use uuid::Uuid;
 
+use crate::config::InstructManagerConfig;
+use crate::core::core_thread::forward::ForwardPolicy;
+use crate::core::core_thread::instruct_manager::{
+    deliver_instruct, search_instruct, FallbackRoute,
+};
 use crate::core::{
     InstructEncoderImpl, InstructMatcherImpl, OperationRecorderImpl, SubmoduleStoreImpl,
 };
+use crate::entity::operation_record::{InboundEntity, OperationOutcome, RecordKind};
 
 pub async fn simple_instruct_manager_thread(
+    instruct_manager_config: InstructManagerConfig,
     instruct_encoder: InstructEncoderImpl,
     instruct_matcher: InstructMatcherImpl,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    mut instruct_receiver: UnboundedReceiver<InstructEntity>,
+    mut instruct_receiver: UnboundedReceiver<InboundEntity<InstructEntity>>,
 ) -> Result<()> {
     info!("Instruct Manager Thread Start");
+    let fallback_route = FallbackRoute::new(&instruct_manager_config);
+    let forward_policy = ForwardPolicy::new(&instruct_manager_config.config_map)?;
     while let Some(instruct) = instruct_receiver.recv().await {
-        info!("Get Instruct：{:?}", &instruct);
-        operation_recorder.recorder_instruct(&instruct).await?;
-        let mut encoded_instruct: Vec<f32> = Vec::new();
-        match &instruct.instruct {
-            Text(text) => {
-                encoded_instruct.append(instruct_encoder.encode(text).await?.as_mut());
-            }
+        let record_id = Uuid::new_v4().to_string();
+        info!(
+            "Get Instruct {:?}, Receive Manipulate Submodule: {:?}",
+            &record_id, &instruct.entity.info.receive_manipulate_submodule
+        );
+        // 回放的指令已有记录，不再重复记录
+        if !instruct.replayed {
+            operation_recorder
+                .recorder_instruct(&record_id, &instruct.entity)
+                .await?;
         }
-
-        match instruct_matcher.lock().await.search(encoded_instruct).await {
-            Ok(module_name) => {
-                if let Some(module) = submodule_store.lock().await.get(&module_name).await? {
-                    match module.client.text_instruct(instruct).await {
-                        Ok(resp) => match resp.code() {
-                            ResponseCode::Success => debug!("Forward Instruct Success"),
-                            other_resp_code => {
-                                error!("Forward Instruct Fail, Resp Code: {:?}", other_resp_code)
-                            }
-                        },
-                        Err(e) => error!("Forward Instruct Error: {}", e),
-                    }
-                    continue;
-                }
-            }
-            Err(e) => {
-                warn!("Match Instruct Handler Error: {}", e);
-            }
+        let mut outcome = OperationOutcome::new(&record_id, RecordKind::Instruct);
+        let match_result = search_instruct(
+            &instruct_encoder,
+            &instruct_matcher,
+            &instruct.entity,
+            &mut outcome,
+        )
+        .await;
+        let replayed = instruct.replayed;
+        deliver_instruct(
+            &submodule_store,
+            &fallback_route,
+            match_result,
+            instruct,
+            &mut outcome,
+            &forward_policy,
+        )
+        .await?;
+        if !replayed {
+            operation_recorder.recorder_outcome(&outcome).await?;
         }
     }
     Ok(())
@@ -6,21 +6,29 @@ use tracing::error;
 
 pub use simple::simple_manipulate_manager_thread;
 
+use crate::config::ManipulateManagerConfig;
 use crate::core::{ManipulateManagerFn, OperationRecorderImpl, SubmoduleStoreImpl};
+use crate::entity::operation_record::InboundEntity;
 use crate::{CANCELLATION_TOKEN, CLOSE_SENDER};
 
 mod simple;
 
 pub fn manipulate_manager_thread(
     manipulate_manager_fn: Box<ManipulateManagerFn>,
+    manipulate_manager_config: ManipulateManagerConfig,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    manipulate_receiver: UnboundedReceiver<ManipulateEntity>,
+    manipulate_receiver: UnboundedReceiver<InboundEntity<ManipulateEntity>>,
 ) -> Result<()> {
     let close_sender = CLOSE_SENDER.get().unwrap().upgrade().unwrap();
     spawn(async move {
-        if let Err(e) =
-            manipulate_manager_fn(submodule_store, operation_recorder, manipulate_receiver).await
+        if let Err(e) = manipulate_manager_fn(
+            manipulate_manager_config,
+            submodule_store,
+            operation_recorder,
+            manipulate_receiver,
+        )
+        .await
         {
             error!("Manipulate Manager Thread Error: {}", e);
             CANCELLATION_TOKEN.cancel();
@@ -1,71 +1,112 @@
+use std::time::Instant;
+
 use anyhow::Result;
 use nihility_common::{ManipulateData, ManipulateEntity, ManipulateType, ResponseCode};
 use tokio::sync::mpsc::UnboundedReceiver;
 use tracing::{debug, error, info};
+use uuid::Uuid;
 
+use crate::config::ManipulateManagerConfig;
+use crate::core::core_thread::forward::{forward_to_submodule, ForwardPolicy, ForwardResult};
 use crate::core::{OperationRecorderImpl, SubmoduleStoreImpl};
+use crate::entity::operation_record::{InboundEntity, OperationOutcome, RecordKind};
 
 pub async fn simple_manipulate_manager_thread(
+    manipulate_manager_config: ManipulateManagerConfig,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    mut manipulate_receiver: UnboundedReceiver<ManipulateEntity>,
+    mut manipulate_receiver: UnboundedReceiver<InboundEntity<ManipulateEntity>>,
 ) -> Result<()> {
-    info!("Manipulate Manager Thread Start");
-    while let Some(manipulate) = manipulate_receiver.recv().await {
-        info!("Get Manipulate：{:?}", &manipulate);
-        operation_recorder.recorder_manipulate(&manipulate).await?;
+    let forward_policy = ForwardPolicy::new(&manipulate_manager_config.config_map)?;
+    info!(
+        "Manipulate Manager Thread Start, Forward Policy: {:?}",
+        forward_policy
+    );
+    while let Some(InboundEntity {
+        entity: manipulate,
+        replayed,
+    }) = manipulate_receiver.recv().await
+    {
+        let record_id = Uuid::new_v4().to_string();
+        info!(
+            "Get Manipulate {:?}, Use Submodule: {:?}",
+            &record_id, &manipulate.info.use_module_name
+        );
+        // 回放的操作已有记录，不再重复记录
+        if !replayed {
+            operation_recorder
+                .recorder_manipulate(&record_id, &manipulate)
+                .await?;
+        }
+        let mut outcome = OperationOutcome::new(&record_id, RecordKind::Manipulate);
+        let use_module_name = manipulate.info.use_module_name.to_string();
+        outcome.matched_submodule = Some(use_module_name.to_string());
         if let ManipulateType::OfflineType = &manipulate.info.manipulate_type {
             error!("Offline Type Manipulate Cannot Forward")
         }
-        if let Some(module) = submodule_store
-            .lock()
-            .await
-            .get(&manipulate.info.use_module_name)
-            .await?
-        {
-            match &manipulate.manipulate {
-                ManipulateData::Text(_) => {
-                    match module.client.text_display_manipulate(manipulate).await {
-                        Ok(resp) => match resp.code() {
-                            ResponseCode::Success => debug!("Send Text Display Manipulate Success"),
-                            other_resp_code => error!(
-                                "Send Text Display Manipulate Fail, Resp Code: {:?}",
-                                other_resp_code
-                            ),
-                        },
-                        Err(e) => error!("Send Text Display Manipulate Error: {}", e),
+        let manipulate_name = match &manipulate.manipulate {
+            ManipulateData::Text(_) => "Text Display",
+            ManipulateData::Simple => "Simple",
+            ManipulateData::ConnectionParams(_) => "Direct Connection",
+        };
+        let forward_start = Instant::now();
+        match forward_to_submodule(
+            &submodule_store,
+            &use_module_name,
+            &forward_policy,
+            |client| {
+                let manipulate = manipulate.clone();
+                async move {
+                    match &manipulate.manipulate {
+                        ManipulateData::Text(_) => client.text_display_manipulate(manipulate).await,
+                        ManipulateData::Simple => client.simple_manipulate(manipulate).await,
+                        ManipulateData::ConnectionParams(_) => {
+                            client.direct_connection_manipulate(manipulate).await
+                        }
                     }
                 }
-                ManipulateData::Simple => match module.client.simple_manipulate(manipulate).await {
-                    Ok(resp) => match resp.code() {
-                        ResponseCode::Success => debug!("Send Simple Manipulate Success"),
-                        other_resp_code => error!(
-                            "Send Simple Manipulate Fail, Resp Code: {:?}",
-                            other_resp_code
-                        ),
-                    },
-                    Err(e) => error!("Send Simple Manipulate Error: {}", e),
-                },
-                ManipulateData::ConnectionParams(_) => {
-                    match module.client.direct_connection_manipulate(manipulate).await {
-                        Ok(resp) => match resp.code() {
+            },
+        )
+        .await?
+        {
+            ForwardResult::NotRegistered => {
+                error!(
+                    "Expect Use Submodule Name {:?} Cannot Find In Register Submodule",
+                    &use_module_name
+                );
+                outcome.error = Some(format!("Submodule {:?} Not Registered", &use_module_name));
+            }
+            ForwardResult::CircuitOpen => {
+                error!(
+                    "Submodule {:?} Circuit Open, Drop {} Manipulate",
+                    &use_module_name, manipulate_name
+                );
+                outcome.error = Some(format!("Submodule {:?} Circuit Open", &use_module_name));
+            }
+            ForwardResult::Completed(forward_result) => {
+                outcome.forward_latency = Some(forward_start.elapsed().as_millis() as u64);
+                match forward_result {
+                    Ok(resp) => {
+                        outcome.response_code = Some(format!("{:?}", resp.code()));
+                        match resp.code() {
                             ResponseCode::Success => {
-                                debug!("Send Direct Connection Manipulate Success")
+                                debug!("Send {} Manipulate Success", manipulate_name)
                             }
                             other_resp_code => error!(
-                                "Send Direct Connection Manipulate Fail, Resp Code: {:?}",
-                                other_resp_code
+                                "Send {} Manipulate Fail, Resp Code: {:?}",
+                                manipulate_name, other_resp_code
                             ),
-                        },
-                        Err(e) => error!("Send Direct Connection Manipulate Error: {}", e),
+                        }
+                    }
+                    Err(e) => {
+                        error!("Send {} Manipulate Error: {}", manipulate_name, e);
+                        outcome.error = Some(e.to_string());
                     }
                 }
             }
-        } else {
-            error!(
-                "Expect Use Submodule Name {:?} Cannot Find In Register Submodule",
-                &manipulate.info.use_module_name
-            )
+        }
+        if !replayed {
+            operation_recorder.recorder_outcome(&outcome).await?;
         }
     }
     Ok(())
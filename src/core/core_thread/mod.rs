@@ -1,5 +1,6 @@
 pub use heartbeat_manager::heartbeat_manager_thread;
 
+pub mod forward;
 pub mod heartbeat_manager;
 pub mod instruct_manager;
 pub mod manipulate_manager;
@@ -10,17 +10,21 @@ use crate::core::{
     InstructEncoderImpl, InstructMatcherImpl, OperationRecorderImpl, SubmoduleManagerFn,
     SubmoduleStoreImpl,
 };
+use crate::entity::operation_record::InboundEntity;
 use crate::{CANCELLATION_TOKEN, CLOSE_SENDER};
 
 mod simple;
 
+/// 定期清理匹配器中不属于已注册子模块的指令点的间隔
+static RECONCILE_TIME: u64 = 300;
+
 pub fn submodule_manager_thread(
     submodule_manager_fn: Box<SubmoduleManagerFn>,
     instruct_encoder: InstructEncoderImpl,
     instruct_matcher: InstructMatcherImpl,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    module_operate_receiver: UnboundedReceiver<ModuleOperate>,
+    module_operate_receiver: UnboundedReceiver<InboundEntity<ModuleOperate>>,
 ) -> Result<()> {
     let close_sender = CLOSE_SENDER.get().unwrap().upgrade().unwrap();
     spawn(async move {
@@ -1,16 +1,19 @@
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
 use nihility_common::{remove_submodule_public_key, ModuleOperate, OperateType};
+use tokio::select;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+use crate::core::core_thread::submodule_manager::RECONCILE_TIME;
 use crate::core::instruct_matcher::PointPayload;
 use crate::core::{
     InstructEncoderImpl, InstructMatcherImpl, OperationRecorderImpl, SubmoduleStoreImpl,
 };
+use crate::entity::operation_record::InboundEntity;
 use crate::entity::submodule::Submodule;
 
 pub async fn simple_submodule_manager_thread(
@@ -18,13 +21,41 @@ pub async fn simple_submodule_manager_thread(
     instruct_matcher: InstructMatcherImpl,
     submodule_store: SubmoduleStoreImpl,
     operation_recorder: OperationRecorderImpl,
-    mut module_operate_receiver: UnboundedReceiver<ModuleOperate>,
+    mut module_operate_receiver: UnboundedReceiver<InboundEntity<ModuleOperate>>,
 ) -> Result<()> {
     info!("Simple Submodule Manager Thread Start");
-    while let Some(module_operate) = module_operate_receiver.recv().await {
-        operation_recorder
-            .recorder_module_operate(&module_operate)
-            .await?;
+    // 第一次清理延后一个间隔，给启动前已注册的子模块留出重新注册、复用持久化指令点的时间
+    let reconcile_period = Duration::from_secs(RECONCILE_TIME);
+    let mut reconcile_interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + reconcile_period,
+        reconcile_period,
+    );
+    loop {
+        let module_operate = select! {
+            module_operate = module_operate_receiver.recv() => match module_operate {
+                Some(InboundEntity { entity, replayed }) => {
+                    // 回放的操作已有记录，不再重复记录
+                    if !replayed {
+                        operation_recorder
+                            .recorder_module_operate(&Uuid::new_v4().to_string(), &entity)
+                            .await?;
+                    }
+                    entity
+                }
+                None => break,
+            },
+            _ = reconcile_interval.tick() => {
+                if let Err(e) = reconcile_instruct_points(
+                    instruct_matcher.clone(),
+                    submodule_store.clone(),
+                )
+                .await
+                {
+                    error!("Reconcile Instruct Points Error: {}", e)
+                }
+                continue;
+            },
+        };
         match module_operate.operate_type {
             OperateType::Register => match register_submodule(
                 instruct_encoder.clone(),
@@ -82,6 +113,23 @@ pub async fn simple_submodule_manager_thread(
     Ok(())
 }
 
+/// 移除匹配器中不属于已注册子模块的指令点
+async fn reconcile_instruct_points(
+    instruct_matcher: InstructMatcherImpl,
+    submodule_store: SubmoduleStoreImpl,
+) -> Result<()> {
+    let submodule_names = submodule_store.lock().await.get_submodule_names().await?;
+    debug!(
+        "Reconcile Instruct Points, Submodules: {:?}",
+        &submodule_names
+    );
+    instruct_matcher
+        .lock()
+        .await
+        .retain_submodules(&submodule_names)
+        .await
+}
+
 async fn update_submodule(
     instruct_encoder: InstructEncoderImpl,
     instruct_matcher: InstructMatcherImpl,
@@ -127,13 +175,18 @@ async fn update_submodule(
             }
         }
     }
-    // 将新增指令编码
-    for (instruct, _) in new_instruct.clone() {
-        new_instruct.insert(
-            instruct.to_string(),
-            instruct_encoder.encode(&instruct).await?,
-        );
-    }
+    // 将新增指令批量编码，编码失败的指令不插入，下次更新时重新尝试
+    let encode_instructs = new_instruct.keys().cloned().collect::<Vec<String>>();
+    let new_instruct = encode_instructs_skip_failed(
+        &instruct_encoder,
+        &instruct_matcher,
+        &module_operate.name,
+        &encode_instructs
+            .iter()
+            .map(|instruct| instruct.as_str())
+            .collect::<Vec<&str>>(),
+    )
+    .await;
     // 将新增的指令负载点存入，然后在InstructMatcher上移除需要删除指令对应的点，最后插入新增指令的点
     let mut insert_points = Vec::<PointPayload>::new();
     for (instruct, encode_result) in new_instruct {
@@ -214,6 +267,48 @@ async fn offline_submodule(
     Ok(module_operate.name.to_string())
 }
 
+/// 批量编码指令，整批失败时逐条编码，跳过编码失败的指令并输出日志，
+/// 匹配器不使用编码时不编码，返回空编码
+async fn encode_instructs_skip_failed(
+    instruct_encoder: &InstructEncoderImpl,
+    instruct_matcher: &InstructMatcherImpl,
+    submodule_name: &str,
+    instructs: &[&str],
+) -> HashMap<String, Vec<f32>> {
+    let mut encode_results = HashMap::<String, Vec<f32>>::new();
+    if !instruct_matcher.lock().await.requires_encode() {
+        for instruct in instructs {
+            encode_results.insert(instruct.to_string(), Vec::new());
+        }
+        return encode_results;
+    }
+    match instruct_encoder.encode_batch(instructs).await {
+        Ok(encodes) => {
+            for (instruct, encode) in instructs.iter().zip(encodes) {
+                encode_results.insert(instruct.to_string(), encode);
+            }
+        }
+        Err(e) => {
+            warn!(
+                "Batch Encode Submodule {:?} Instructs Error: {}, Encode One By One",
+                submodule_name, e
+            );
+            for instruct in instructs {
+                match instruct_encoder.encode(instruct).await {
+                    Ok(encode) => {
+                        encode_results.insert(instruct.to_string(), encode);
+                    }
+                    Err(e) => error!(
+                        "Skip Submodule {:?} Instruct {:?}, Encode Error: {}",
+                        submodule_name, instruct, e
+                    ),
+                }
+            }
+        }
+    }
+    encode_results
+}
+
 async fn register_submodule(
     instruct_encoder: InstructEncoderImpl,
     instruct_matcher: InstructMatcherImpl,
@@ -237,11 +332,53 @@ async fn register_submodule(
             &module_operate.name
         ));
     }
+    // 复用匹配器中已持久化且维度一致的指令点，避免重复编码
+    let encode_size = instruct_encoder.encode_size().await as usize;
+    let mut remove_points = Vec::<PointPayload>::new();
+    let mut persisted_points = HashMap::<String, PointPayload>::new();
+    for point in instruct_matcher
+        .lock()
+        .await
+        .get_points(&register_submodule_name)
+        .await?
+    {
+        if point.encode.len() == encode_size && !persisted_points.contains_key(&point.instruct) {
+            persisted_points.insert(point.instruct.to_string(), point);
+        } else {
+            remove_points.push(point);
+        }
+    }
     let original_default_instruct_map = submodule.default_instruct_map.clone();
+    let mut encode_instructs = Vec::<&str>::new();
     for (instruct, _) in original_default_instruct_map.iter() {
-        let encode_result = instruct_encoder.encode(instruct).await?;
+        if let Some(point_payload) = persisted_points.remove(instruct) {
+            debug!(
+                "{:?} Reuse Persisted Instruct Point Payload: {:?}",
+                &module_operate.name, &point_payload.uuid
+            );
+            submodule
+                .default_instruct_map
+                .insert(instruct.to_string(), point_payload);
+            continue;
+        }
+        encode_instructs.push(instruct);
+    }
+    // 未能复用的指令批量编码，编码失败的指令不参与匹配，不影响子模块注册
+    let encode_results = encode_instructs_skip_failed(
+        &instruct_encoder,
+        &instruct_matcher,
+        &module_operate.name,
+        &encode_instructs,
+    )
+    .await;
+    for instruct in encode_instructs {
+        if !encode_results.contains_key(instruct) {
+            submodule.default_instruct_map.remove(instruct);
+        }
+    }
+    for (instruct, encode_result) in encode_results {
         let point_payload = PointPayload {
-            encode: encode_result.clone(),
+            encode: encode_result,
             submodule_id: register_submodule_name.to_string(),
             instruct: instruct.to_string(),
             uuid: Uuid::new_v4().to_string(),
@@ -256,7 +393,11 @@ async fn register_submodule(
         points.push(point_payload);
     }
 
+    remove_points.extend(persisted_points.into_values());
+
     submodule_store.lock().await.insert(submodule).await?;
-    instruct_matcher.lock().await.append_points(points).await?;
+    let mut matcher = instruct_matcher.lock().await;
+    matcher.remove_points(remove_points).await?;
+    matcher.append_points(points).await?;
     Ok(register_submodule_name)
 }
@@ -0,0 +1,445 @@
+use std::fs::{read_to_string, rename, write, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hashlink::LruCache;
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tracing::{debug, info, warn};
+
+use crate::config::InstructEncoderConfig;
+use crate::core::instruct_encoder::{
+    create_inner_instruct_encoder, InstructEncoder, CACHE_CAPACITY_FIELD,
+};
+
+/// 缓存持久化文件，每行一条缓存记录，为空时不持久化
+pub const CACHE_PATH_FIELD: &str = "cache_path";
+/// 每查询该次数输出一次缓存命中统计
+const STATS_LOG_INTERVAL: u64 = 1000;
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    key: String,
+    encode: Vec<f32>,
+}
+
+struct EncodeCache {
+    lru_cache: LruCache<String, Vec<f32>>,
+    /// 持久化文件中的记录数，超过容量两倍时重写文件
+    persisted_lines: usize,
+}
+
+/// 缓存持久化文件，读写均为同步接口，需在阻塞线程池中调用
+struct CacheFile {
+    cache_path: String,
+    write_lock: Mutex<()>,
+}
+
+impl CacheFile {
+    /// 读取属于当前模型的记录，损坏的行直接跳过
+    fn load(&self, cache_identity: &str, lru_cache: &mut LruCache<String, Vec<f32>>) -> Result<()> {
+        if !Path::new(&self.cache_path).exists() {
+            return Ok(());
+        }
+        let key_prefix = format!("{}\n", cache_identity);
+        for line in read_to_string(&self.cache_path)?.lines() {
+            match serde_json::from_str::<CacheEntry>(line) {
+                Ok(entry) if entry.key.starts_with(&key_prefix) => {
+                    lru_cache.insert(entry.key, entry.encode);
+                }
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Skip Broken Encode Cache Line In {:?}: {}",
+                    &self.cache_path, e
+                ),
+            }
+        }
+        info!(
+            "Load {} Encode Cache Entries From {:?}",
+            lru_cache.len(),
+            &self.cache_path
+        );
+        Ok(())
+    }
+
+    fn append(&self, content: &str) -> Result<()> {
+        let _write_lock = self
+            .write_lock
+            .lock()
+            .map_err(|e| anyhow!("Encode Cache File Lock Error: {}", e))?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.cache_path)?
+            .write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    /// 先写入临时文件再重命名，避免写入中断导致文件损坏
+    fn rewrite(&self, content: &str) -> Result<()> {
+        let _write_lock = self
+            .write_lock
+            .lock()
+            .map_err(|e| anyhow!("Encode Cache File Lock Error: {}", e))?;
+        let tmp_path = format!("{}.tmp", &self.cache_path);
+        write(&tmp_path, content)?;
+        rename(&tmp_path, &self.cache_path)?;
+        Ok(())
+    }
+}
+
+/// 忽略首尾空白并将连续空白合并为一个空格，仅用于构建缓存键
+fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn cache_lines<'a>(entries: impl Iterator<Item = (&'a String, &'a Vec<f32>)>) -> Result<String> {
+    let mut content = String::new();
+    for (key, encode) in entries {
+        content.push_str(&serde_json::to_string(&CacheEntry {
+            key: key.to_string(),
+            encode: encode.clone(),
+        })?);
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+/// 包装任意编码器的LRU缓存，以模型标识、编码维度与规范化后的文本作为键，编码时仍使用原始文本，
+/// 配置了`cache_path`时新增的编码追加写入文件，重启后重新加载
+pub struct CachedInstructEncoder {
+    inner_encoder: Box<dyn InstructEncoder + Send + Sync>,
+    /// 缓存键及持久化记录的前缀，由内部编码器的模型标识与编码维度组成
+    cache_identity: String,
+    encode_cache: Mutex<EncodeCache>,
+    cache_file: Option<Arc<CacheFile>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedInstructEncoder {
+    pub async fn new(
+        inner_encoder: Box<dyn InstructEncoder + Send + Sync>,
+        cache_capacity: usize,
+        cache_path: Option<String>,
+    ) -> Result<Self> {
+        let cache_identity = format!(
+            "{}:{}",
+            inner_encoder.model_identity(),
+            inner_encoder.encode_size().await
+        );
+        info!(
+            "Encode Cache Capacity: {}, Path: {:?}, Cache Identity: {:?}",
+            cache_capacity, cache_path, cache_identity
+        );
+        let cache_file = cache_path.map(|cache_path| {
+            Arc::new(CacheFile {
+                cache_path,
+                write_lock: Mutex::new(()),
+            })
+        });
+        let mut lru_cache = LruCache::<String, Vec<f32>>::new(cache_capacity.max(1));
+        let mut persisted_lines = 0;
+        if let Some(cache_file) = &cache_file {
+            let cache_file = cache_file.clone();
+            let identity = cache_identity.to_string();
+            lru_cache = spawn_blocking(move || -> Result<LruCache<String, Vec<f32>>> {
+                cache_file.load(&identity, &mut lru_cache)?;
+                // 加载后重写一次，去除其他模型、已淘汰与损坏的记录
+                cache_file.rewrite(&cache_lines(lru_cache.iter())?)?;
+                Ok(lru_cache)
+            })
+            .await??;
+            persisted_lines = lru_cache.len();
+        }
+        Ok(CachedInstructEncoder {
+            inner_encoder,
+            cache_identity,
+            encode_cache: Mutex::new(EncodeCache {
+                lru_cache,
+                persisted_lines,
+            }),
+            cache_file,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    fn cache_key(&self, input: &str) -> String {
+        format!("{}\n{}", self.cache_identity, normalize_text(input))
+    }
+
+    /// 新增的编码追加到持久化文件，文件中的记录过多时按最久未使用到最近使用的顺序重写
+    async fn insert_entries(&self, entries: Vec<(String, Vec<f32>)>) -> Result<()> {
+        let cache_file = match &self.cache_file {
+            None => {
+                let mut encode_cache = self
+                    .encode_cache
+                    .lock()
+                    .map_err(|e| anyhow!("Encode Cache Lock Error: {}", e))?;
+                for (key, encode) in entries {
+                    encode_cache.lru_cache.insert(key, encode);
+                }
+                return Ok(());
+            }
+            Some(cache_file) => cache_file.clone(),
+        };
+        let (content, rewrite) = {
+            let mut encode_cache = self
+                .encode_cache
+                .lock()
+                .map_err(|e| anyhow!("Encode Cache Lock Error: {}", e))?;
+            let content = cache_lines(entries.iter().map(|(key, encode)| (key, encode)))?;
+            encode_cache.persisted_lines += entries.len();
+            for (key, encode) in entries {
+                encode_cache.lru_cache.insert(key, encode);
+            }
+            if encode_cache.persisted_lines > encode_cache.lru_cache.capacity() * 2 {
+                encode_cache.persisted_lines = encode_cache.lru_cache.len();
+                (cache_lines(encode_cache.lru_cache.iter())?, true)
+            } else {
+                (content, false)
+            }
+        };
+        spawn_blocking(move || match rewrite {
+            true => {
+                debug!("Rewrite Encode Cache File {:?}", &cache_file.cache_path);
+                cache_file.rewrite(&content)
+            }
+            false => cache_file.append(&content),
+        })
+        .await??;
+        Ok(())
+    }
+
+    fn record_lookups(&self, hit_count: u64, miss_count: u64) {
+        let hits = self.hits.fetch_add(hit_count, Ordering::Relaxed) + hit_count;
+        let misses = self.misses.fetch_add(miss_count, Ordering::Relaxed) + miss_count;
+        let lookups = hits + misses;
+        debug!("Encode Cache Hits: {}, Misses: {}", hits, misses);
+        if lookups / STATS_LOG_INTERVAL != (lookups - hit_count - miss_count) / STATS_LOG_INTERVAL {
+            info!(
+                "Encode Cache Hits: {}, Misses: {}, Hit Rate: {:.2}%",
+                hits,
+                misses,
+                hits as f64 * 100.0 / lookups as f64
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl InstructEncoder for CachedInstructEncoder {
+    async fn init(instruct_encoder_config: &InstructEncoderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let cache_capacity = match instruct_encoder_config.config_map.get(CACHE_CAPACITY_FIELD) {
+            None => {
+                return Err(anyhow!(
+                    "Required configuration {:?} is missing",
+                    CACHE_CAPACITY_FIELD
+                ))
+            }
+            Some(cache_capacity) => cache_capacity.parse::<usize>()?,
+        };
+        let cache_path = instruct_encoder_config
+            .config_map
+            .get(CACHE_PATH_FIELD)
+            .filter(|cache_path| !cache_path.is_empty())
+            .map(|cache_path| cache_path.to_string());
+        CachedInstructEncoder::new(
+            create_inner_instruct_encoder(instruct_encoder_config).await?,
+            cache_capacity,
+            cache_path,
+        )
+        .await
+    }
+
+    async fn encode(&self, input: &str) -> Result<Vec<f32>> {
+        let mut results = self.encode_batch(&[input]).await?;
+        results
+            .pop()
+            .ok_or(anyhow!("Encode Result Transform Error"))
+    }
+
+    /// 同一批中规范化后相同的文本只编码第一条
+    async fn encode_batch(&self, inputs: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let keys = inputs
+            .iter()
+            .map(|input| self.cache_key(input))
+            .collect::<Vec<String>>();
+        let mut results = Vec::<Option<Vec<f32>>>::with_capacity(inputs.len());
+        let mut miss_indexes = Vec::<usize>::new();
+        {
+            let mut encode_cache = self
+                .encode_cache
+                .lock()
+                .map_err(|e| anyhow!("Encode Cache Lock Error: {}", e))?;
+            for (index, key) in keys.iter().enumerate() {
+                let cached = encode_cache.lru_cache.get(key).cloned();
+                if cached.is_none() && !miss_indexes.iter().any(|miss| keys[*miss].eq(key)) {
+                    miss_indexes.push(index);
+                }
+                results.push(cached);
+            }
+        }
+        let miss_count = results.iter().filter(|result| result.is_none()).count() as u64;
+        self.record_lookups(inputs.len() as u64 - miss_count, miss_count);
+        if miss_indexes.is_empty() {
+            return Ok(results.into_iter().flatten().collect());
+        }
+
+        let miss_inputs = miss_indexes
+            .iter()
+            .map(|index| inputs[*index])
+            .collect::<Vec<&str>>();
+        let miss_encodes = self.inner_encoder.encode_batch(&miss_inputs).await?;
+        if miss_encodes.len() != miss_inputs.len() {
+            return Err(anyhow!("Encode Result Transform Error"));
+        }
+        let miss_entries = miss_indexes
+            .iter()
+            .map(|index| keys[*index].to_string())
+            .zip(miss_encodes)
+            .collect::<Vec<(String, Vec<f32>)>>();
+        let mut encode_results = Vec::<Vec<f32>>::with_capacity(inputs.len());
+        for (result, key) in results.into_iter().zip(keys.iter()) {
+            match result {
+                Some(encode) => encode_results.push(encode),
+                None => match miss_entries.iter().find(|(miss_key, _)| miss_key.eq(key)) {
+                    None => return Err(anyhow!("Encode Result Transform Error")),
+                    Some((_, encode)) => encode_results.push(encode.clone()),
+                },
+            }
+        }
+        self.insert_entries(miss_entries).await?;
+        Ok(encode_results)
+    }
+
+    async fn encode_size(&self) -> u64 {
+        self.inner_encoder.encode_size().await
+    }
+
+    /// 缓存不改变编码结果，沿用内部编码器的模型标识
+    fn model_identity(&self) -> String {
+        self.inner_encoder.model_identity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_file;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// 记录实际编码的文本，编码结果为文本的字节数
+    struct RecordingEncoder {
+        model_identity: String,
+        inputs: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl InstructEncoder for RecordingEncoder {
+        async fn init(_instruct_encoder_config: &InstructEncoderConfig) -> Result<Self>
+        where
+            Self: Sized + Send + Sync,
+        {
+            Err(anyhow!("RecordingEncoder Cannot Init From Config"))
+        }
+
+        async fn encode(&self, input: &str) -> Result<Vec<f32>> {
+            self.inputs.lock().unwrap().push(input.to_string());
+            Ok(vec![input.len() as f32])
+        }
+
+        async fn encode_size(&self) -> u64 {
+            1
+        }
+
+        fn model_identity(&self) -> String {
+            self.model_identity.to_string()
+        }
+    }
+
+    async fn create_cached_encoder(
+        model_identity: &str,
+        cache_capacity: usize,
+        cache_path: Option<String>,
+    ) -> (CachedInstructEncoder, Arc<Mutex<Vec<String>>>) {
+        let inputs = Arc::new(Mutex::new(Vec::<String>::new()));
+        let inner_encoder = RecordingEncoder {
+            model_identity: model_identity.to_string(),
+            inputs: inputs.clone(),
+        };
+        let cached_encoder =
+            CachedInstructEncoder::new(Box::new(inner_encoder), cache_capacity, cache_path)
+                .await
+                .unwrap();
+        (cached_encoder, inputs)
+    }
+
+    #[tokio::test]
+    async fn key_ignores_whitespace_but_encodes_raw_input() {
+        let (cached_encoder, inputs) = create_cached_encoder("model", 4, None).await;
+        assert_eq!(
+            cached_encoder.cache_key("  打开   客厅 灯 "),
+            "model:1\n打开 客厅 灯"
+        );
+        let first = cached_encoder.encode("打开  灯").await.unwrap();
+        let second = cached_encoder.encode(" 打开 灯 ").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(*inputs.lock().unwrap(), vec!["打开  灯"]);
+        assert_eq!(cached_encoder.model_identity(), "model");
+    }
+
+    #[tokio::test]
+    async fn batch_encodes_duplicates_once() {
+        let (cached_encoder, inputs) = create_cached_encoder("model", 4, None).await;
+        let results = cached_encoder
+            .encode_batch(&["a", "b", "a "])
+            .await
+            .unwrap();
+        assert_eq!(results, vec![vec![1.0], vec![1.0], vec![1.0]]);
+        assert_eq!(*inputs.lock().unwrap(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let (cached_encoder, inputs) = create_cached_encoder("model", 2, None).await;
+        for input in ["a", "b", "a", "c", "a", "b"] {
+            cached_encoder.encode(input).await.unwrap();
+        }
+        // 编码c时b最久未使用被淘汰，a一直命中
+        assert_eq!(*inputs.lock().unwrap(), vec!["a", "b", "c", "b"]);
+    }
+
+    #[tokio::test]
+    async fn persisted_entries_reload_only_for_same_identity() {
+        let cache_path = std::env::temp_dir()
+            .join(format!("nihility_encode_cache_{}.jsonl", Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        {
+            let (cached_encoder, _) =
+                create_cached_encoder("model", 4, Some(cache_path.clone())).await;
+            cached_encoder.encode("a").await.unwrap();
+        }
+        let (cached_encoder, inputs) =
+            create_cached_encoder("model", 4, Some(cache_path.clone())).await;
+        cached_encoder.encode("a").await.unwrap();
+        assert!(inputs.lock().unwrap().is_empty());
+
+        let (cached_encoder, inputs) =
+            create_cached_encoder("other", 4, Some(cache_path.clone())).await;
+        cached_encoder.encode("a").await.unwrap();
+        assert_eq!(*inputs.lock().unwrap(), vec!["a"]);
+        remove_file(cache_path).unwrap();
+    }
+}
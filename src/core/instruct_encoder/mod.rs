@@ -1,10 +1,16 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
-use crate::config::InstructEncoderConfig;
+use crate::config::{InstructEncoderConfig, InstructEncoderType};
+use crate::core::instruct_encoder::cached::CachedInstructEncoder;
+use crate::core::instruct_encoder::sentence_transformers::SentenceTransformers;
 
+pub mod cached;
 pub mod sentence_transformers;
 
+/// 编码缓存的最大条数，未配置或为0时不启用缓存
+pub const CACHE_CAPACITY_FIELD: &str = "cache_capacity";
+
 #[async_trait]
 pub trait InstructEncoder {
     async fn init(instruct_encoder_config: &InstructEncoderConfig) -> Result<Self>
@@ -13,5 +19,45 @@ pub trait InstructEncoder {
 
     async fn encode(&self, input: &str) -> Result<Vec<f32>>;
 
+    /// 批量编码，结果顺序与输入一致，默认逐条编码
+    async fn encode_batch(&self, inputs: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let mut results = Vec::<Vec<f32>>::with_capacity(inputs.len());
+        for input in inputs {
+            results.push(self.encode(input).await?);
+        }
+        Ok(results)
+    }
+
     async fn encode_size(&self) -> u64;
+
+    /// 模型及影响编码结果的配置的标识，标识不同的编码结果不能混用
+    fn model_identity(&self) -> String;
+}
+
+/// 配置了`cache_capacity`时使用带缓存的编码器包装实际编码器
+pub async fn create_instruct_encoder(
+    instruct_encoder_config: &InstructEncoderConfig,
+) -> Result<Box<dyn InstructEncoder + Send + Sync>> {
+    match instruct_encoder_config
+        .config_map
+        .get(CACHE_CAPACITY_FIELD)
+        .map(|cache_capacity| cache_capacity.parse::<usize>())
+        .transpose()?
+    {
+        None | Some(0) => create_inner_instruct_encoder(instruct_encoder_config).await,
+        Some(_) => Ok(Box::new(
+            CachedInstructEncoder::init(instruct_encoder_config).await?,
+        )),
+    }
+}
+
+/// 创建不带缓存的实际编码器
+pub async fn create_inner_instruct_encoder(
+    instruct_encoder_config: &InstructEncoderConfig,
+) -> Result<Box<dyn InstructEncoder + Send + Sync>> {
+    Ok(match &instruct_encoder_config.instruct_encoder_type {
+        InstructEncoderType::SentenceTransformers => {
+            Box::new(SentenceTransformers::init(instruct_encoder_config).await?)
+        }
+    })
 }
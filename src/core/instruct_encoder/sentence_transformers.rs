@@ -1,23 +1,192 @@
+use std::fs::metadata;
 use std::ops::Deref;
 use std::panic::catch_unwind;
+use std::time::UNIX_EPOCH;
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use ndarray::{Array1, Axis};
+use ndarray::{Array2, ArrayView2, Axis, Ix3};
 use ort::{inputs, CPUExecutionProvider, GraphOptimizationLevel, Session};
-use tokenizers::Tokenizer;
-use tracing::debug;
+use tokenizers::{
+    PostProcessor, Tokenizer, TruncationDirection, TruncationParams, TruncationStrategy,
+};
+use tracing::{debug, info};
 
 use crate::config::InstructEncoderConfig;
 use crate::core::instruct_encoder::InstructEncoder;
+use crate::core::instruct_matcher::normalize;
 
-pub const ENCODE_SIZE: u64 = 512;
 pub const MODULE_PATH: &str = "module_path";
 pub const MODULE_NAME: &str = "model_name";
+/// 池化方式，可选`cls`、`mean`、`max`，默认`mean`，与旧版本的编码结果一致，
+/// BGE系列模型建议配置为`cls`并开启归一化，修改后已持久化的指令编码会因模型标识变化而失效
+pub const POOLING_FIELD: &str = "pooling";
+/// 是否对编码结果进行L2归一化，默认`false`
+pub const NORMALIZE_FIELD: &str = "normalize";
+/// 是否加入`[CLS]`等特殊token，`cls`池化时默认`true`，其余默认`false`
+pub const ADD_SPECIAL_TOKENS_FIELD: &str = "add_special_tokens";
+/// 包含特殊token在内的最大token数
+pub const MAX_LENGTH_FIELD: &str = "max_length";
+/// 超出最大长度时的处理方式，可选`right`(保留开头)、`left`(保留结尾)、`error`(返回错误)，默认`right`
+pub const TRUNCATION_STRATEGY_FIELD: &str = "truncation_strategy";
+const DEFAULT_MAX_LENGTH: usize = 512;
+/// 输出维度为动态时用于探测编码维度的文本
+const ENCODE_SIZE_PROBE: &str = "encode size probe";
+/// 单次推理的最大文本数，避免指令过多时占用过多内存
+const MAX_BATCH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pooling {
+    /// 取`[CLS]`位置的输出，BGE系列模型使用该方式
+    Cls,
+    /// 按注意力掩码对有效token的输出取平均
+    Mean,
+    /// 按注意力掩码对有效token的输出取最大值
+    Max,
+}
+
+impl Pooling {
+    /// `token_outputs`形状为`[token数, 编码维度]`，`attention_mask`为0的token不参与池化
+    fn pool(&self, token_outputs: ArrayView2<f32>, attention_mask: &[u32]) -> Result<Vec<f32>> {
+        let mut valid_outputs = token_outputs
+            .axis_iter(Axis(0))
+            .zip(attention_mask.iter())
+            .filter(|(_, mask)| **mask != 0)
+            .map(|(output, _)| output);
+        match self {
+            Pooling::Cls => match token_outputs.axis_iter(Axis(0)).next() {
+                None => Err(anyhow!("Encode Result Has No Token Output")),
+                Some(cls_output) => Ok(cls_output.to_vec()),
+            },
+            Pooling::Mean => {
+                let mut result = match valid_outputs.next() {
+                    None => return Err(anyhow!("Encode Result Has No Valid Token Output")),
+                    Some(output) => output.to_owned(),
+                };
+                let mut count = 1.0f32;
+                for output in valid_outputs {
+                    result += &output;
+                    count += 1.0;
+                }
+                Ok(result.iter().map(|x| x / count).collect())
+            }
+            Pooling::Max => {
+                let mut result = match valid_outputs.next() {
+                    None => return Err(anyhow!("Encode Result Has No Valid Token Output")),
+                    Some(output) => output.to_owned(),
+                };
+                for output in valid_outputs {
+                    result.zip_mut_with(&output, |max, x| *max = max.max(*x));
+                }
+                Ok(result.to_vec())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Truncation {
+    /// 截断结尾，保留开头
+    Right,
+    /// 截断开头，保留结尾
+    Left,
+    /// 不截断，超出最大长度时返回错误
+    Error,
+}
+
+/// 以文件大小与修改时间标识模型文件，替换模型文件后已有的编码随之失效
+fn file_fingerprint(file_path: &str) -> Result<String> {
+    let file_metadata = metadata(file_path)?;
+    let modified = file_metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    Ok(format!("{}@{}", file_metadata.len(), modified))
+}
 
 pub struct SentenceTransformers {
     pub ort_session: Session,
     pub tokenizer: Tokenizer,
+    pub pooling: Pooling,
+    pub normalize: bool,
+    pub add_special_tokens: bool,
+    pub max_length: usize,
+    pub truncation: Truncation,
+    /// 编码维度，由模型输出的最后一维决定
+    pub encode_size: u64,
+    pub model_identity: String,
+}
+
+impl SentenceTransformers {
+    /// 将一批文本填充到相同长度后一次推理，填充部分的注意力掩码为0，不参与池化
+    fn run_batch(&self, inputs: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs.to_vec(), self.add_special_tokens)
+            .map_err(|e| anyhow!("Tokenizer Encode Error: {}", e))?;
+        // 其余截断方式已由tokenizer截断到最大长度
+        if let Some(encoding) = encodings.iter().find(|encoding| {
+            self.truncation == Truncation::Error && encoding.len() > self.max_length
+        }) {
+            return Err(anyhow!(
+                "Input Too Long, {} Tokens Exceed Max Length {}",
+                encoding.len(),
+                self.max_length
+            ));
+        }
+        debug!("Encodings: {:?}", &encodings);
+        let max_len = encodings
+            .iter()
+            .map(|encoding| encoding.len())
+            .max()
+            .unwrap_or(0);
+        let pad_id = self
+            .tokenizer
+            .get_padding()
+            .map_or(0, |padding| padding.pad_id as i64);
+        let mut input_ids = Array2::<i64>::from_elem((encodings.len(), max_len), pad_id);
+        let mut token_type_ids = Array2::<i64>::zeros((encodings.len(), max_len));
+        let mut attention_mask = Array2::<i64>::zeros((encodings.len(), max_len));
+        for (i, encoding) in encodings.iter().enumerate() {
+            for (j, id) in encoding.get_ids().iter().enumerate() {
+                input_ids[[i, j]] = *id as i64;
+            }
+            for (j, type_id) in encoding.get_type_ids().iter().enumerate() {
+                token_type_ids[[i, j]] = *type_id as i64;
+            }
+            for (j, mask) in encoding.get_attention_mask().iter().enumerate() {
+                attention_mask[[i, j]] = *mask as i64;
+            }
+        }
+
+        let inputs = inputs![
+            "input_ids" => input_ids,
+            "token_type_ids" => token_type_ids,
+            "attention_mask" => attention_mask,
+        ]?;
+        debug!("Onnx Inputs: {:?}", &inputs);
+        let outputs = self.ort_session.run(inputs)?;
+        let generated_tokens = outputs[0].extract_tensor::<f32>()?;
+        let encode_result = generated_tokens.view();
+        let encode_result = encode_result.deref().view().into_dimensionality::<Ix3>()?;
+
+        let mut results = Vec::<Vec<f32>>::with_capacity(encodings.len());
+        for (token_outputs, encoding) in encode_result.axis_iter(Axis(0)).zip(encodings.iter()) {
+            let mut result = self
+                .pooling
+                .pool(token_outputs, encoding.get_attention_mask())?;
+            if self.normalize {
+                result = normalize(&result);
+            }
+            results.push(result);
+        }
+        debug!(
+            "Encode {} Results Len:{:?}",
+            results.len(),
+            results.first().map(|result| result.len())
+        );
+        Ok(results)
+    }
 }
 
 #[async_trait]
@@ -29,87 +198,185 @@ impl InstructEncoder for SentenceTransformers {
         let model_path = instruct_encoder_config
             .config_map
             .get(MODULE_PATH)
-            .expect("SentenceTransformers Config Field 'MODULE_PATH' Missing");
+            .ok_or(anyhow!(
+                "Required configuration {:?} is missing",
+                MODULE_PATH
+            ))?;
         let model_name = instruct_encoder_config
             .config_map
             .get(MODULE_NAME)
-            .expect("SentenceTransformers Config Field 'MODULE_NAME' Missing");
+            .ok_or(anyhow!(
+                "Required configuration {:?} is missing",
+                MODULE_NAME
+            ))?;
         let onnx_model_path = format!("{}/{}/model.onnx", model_path, model_name);
         let tokenizers_config_path = format!("{}/{}/tokenizer.json", model_path, model_name);
         debug!("Use onnx_model_path: {}", &onnx_model_path);
         debug!("Use tokenizers_config_path: {}", &tokenizers_config_path);
-        if let Err(_) = catch_unwind(|| {
+        match catch_unwind(|| {
             ort::init_from(instruct_encoder_config.ort_lib_path.to_string())
                 .with_execution_providers([CPUExecutionProvider::default().build()])
                 .commit()
         }) {
-            return Err(anyhow!("Ort Init Error, Please Check The Config!"));
+            Err(_) => return Err(anyhow!("Ort Init Error, Please Check The Config!")),
+            Ok(commit_result) => {
+                commit_result?;
+            }
         }
+        let model_fingerprint = format!(
+            "{}+{}",
+            file_fingerprint(&onnx_model_path)?,
+            file_fingerprint(&tokenizers_config_path)?
+        );
         let session = Session::builder()?
             .with_optimization_level(GraphOptimizationLevel::Level1)?
             .with_model_from_file(onnx_model_path)?;
 
-        let tokenizer = Tokenizer::from_file(tokenizers_config_path).unwrap();
+        let mut tokenizer = Tokenizer::from_file(&tokenizers_config_path).map_err(|e| {
+            anyhow!(
+                "Load Tokenizer From {:?} Error: {}",
+                &tokenizers_config_path,
+                e
+            )
+        })?;
+        let max_length = match instruct_encoder_config.config_map.get(MAX_LENGTH_FIELD) {
+            None => DEFAULT_MAX_LENGTH,
+            Some(max_length) => max_length.parse::<usize>()?,
+        };
+        // 最大长度需要容纳特殊token及至少一个文本token，否则tokenizer计算截断长度时会溢出
+        let special_tokens = tokenizer
+            .get_post_processor()
+            .map_or(0, |post_processor| post_processor.added_tokens(false));
+        if max_length <= special_tokens {
+            return Err(anyhow!(
+                "Config {:?} Value {} Must Be Greater Than Special Token Count {}",
+                MAX_LENGTH_FIELD,
+                max_length,
+                special_tokens
+            ));
+        }
+        let truncation = match instruct_encoder_config
+            .config_map
+            .get(TRUNCATION_STRATEGY_FIELD)
+            .map(|truncation| truncation.as_str())
+        {
+            None | Some("right") => Truncation::Right,
+            Some("left") => Truncation::Left,
+            Some("error") => Truncation::Error,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"right\", \"left\" Or \"error\"",
+                    TRUNCATION_STRATEGY_FIELD,
+                    other
+                ))
+            }
+        };
+        // 覆盖tokenizer.json中的截断配置，返回错误时需要完整的token数
+        let truncation_params = match truncation {
+            Truncation::Right => Some(TruncationDirection::Right),
+            Truncation::Left => Some(TruncationDirection::Left),
+            Truncation::Error => None,
+        }
+        .map(|direction| TruncationParams {
+            direction,
+            max_length,
+            strategy: TruncationStrategy::LongestFirst,
+            stride: 0,
+        });
+        tokenizer
+            .with_truncation(truncation_params)
+            .map_err(|e| anyhow!("Tokenizer Truncation Config Error: {}", e))?;
+
+        let pooling = match instruct_encoder_config
+            .config_map
+            .get(POOLING_FIELD)
+            .map(|pooling| pooling.as_str())
+        {
+            Some("cls") => Pooling::Cls,
+            None | Some("mean") => Pooling::Mean,
+            Some("max") => Pooling::Max,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"cls\", \"mean\" Or \"max\"",
+                    POOLING_FIELD,
+                    other
+                ))
+            }
+        };
+        let normalize = match instruct_encoder_config.config_map.get(NORMALIZE_FIELD) {
+            None => false,
+            Some(normalize) => normalize.parse::<bool>()?,
+        };
+        // CLS池化依赖`[CLS]`位置的输出
+        let add_special_tokens = match instruct_encoder_config
+            .config_map
+            .get(ADD_SPECIAL_TOKENS_FIELD)
+        {
+            None => pooling == Pooling::Cls,
+            Some(add_special_tokens) => add_special_tokens.parse::<bool>()?,
+        };
+        info!(
+            "SentenceTransformers Pooling: {:?}, Normalize: {}, Add Special Tokens: {}, Max Length: {}, Truncation: {:?}",
+            pooling, normalize, add_special_tokens, max_length, truncation
+        );
 
-        let encoder = SentenceTransformers {
+        // 模型输出形状为`[batch, token数, 编码维度]`，维度为动态(-1)时实际编码一次获取
+        let output_encode_size = session
+            .outputs
+            .first()
+            .and_then(|output| output.output_type.tensor_dimensions())
+            .and_then(|dimensions| dimensions.last())
+            .copied()
+            .unwrap_or(-1);
+        let mut encoder = SentenceTransformers {
             ort_session: session,
             tokenizer,
+            pooling,
+            normalize,
+            add_special_tokens,
+            max_length,
+            truncation,
+            encode_size: 0,
+            model_identity: format!(
+                "SentenceTransformers:{}/{}:{}:{:?}:{}:{}:{}:{:?}",
+                model_path,
+                model_name,
+                model_fingerprint,
+                pooling,
+                normalize,
+                add_special_tokens,
+                max_length,
+                truncation
+            ),
+        };
+        encoder.encode_size = match u64::try_from(output_encode_size) {
+            Ok(encode_size) if encode_size > 0 => encode_size,
+            _ => encoder.encode(ENCODE_SIZE_PROBE).await?.len() as u64,
         };
+        info!("SentenceTransformers Encode Size: {}", encoder.encode_size);
         Ok(encoder)
     }
 
     async fn encode(&self, input: &str) -> Result<Vec<f32>> {
-        let encoding = self.tokenizer.encode(input, false).unwrap();
-        debug!("Encoding: {:?}", &encoding);
-
-        let input_ids = Array1::from_iter(
-            encoding
-                .get_ids()
-                .iter()
-                .map(|i| *i as i64)
-                .collect::<Vec<_>>(),
-        )
-        .insert_axis(Axis(0));
-
-        let token_type_ids = Array1::from_iter(
-            encoding
-                .get_type_ids()
-                .iter()
-                .map(|i| *i as i64)
-                .collect::<Vec<_>>(),
-        )
-        .insert_axis(Axis(0));
-
-        let attention_mask = Array1::from_iter(
-            encoding
-                .get_attention_mask()
-                .iter()
-                .map(|i| *i as i64)
-                .collect::<Vec<_>>(),
-        )
-        .insert_axis(Axis(0));
+        self.encode_batch(&[input])
+            .await?
+            .pop()
+            .ok_or(anyhow!("Encode Result Transform Error"))
+    }
 
-        let inputs = inputs![
-            "input_ids" => input_ids,
-            "token_type_ids" => token_type_ids,
-            "attention_mask" => attention_mask,
-        ]?;
-        debug!("Onnx Inputs: {:?}", &inputs);
-        let outputs = self.ort_session.run(inputs)?;
-        let generated_tokens = outputs[0].extract_tensor::<f32>()?;
-        let encode_result = generated_tokens.view();
-        let encode_result = encode_result.deref().index_axis(Axis(0), 0);
-
-        return if let Some(result) = encode_result.mean_axis(Axis(0)) {
-            let result = result.iter().cloned().collect::<Vec<f32>>();
-            debug!("Encode Result Len:{:?}", result.len());
-            Ok(result)
-        } else {
-            Err(anyhow!("Encode Result Transform Error"))
-        };
+    async fn encode_batch(&self, inputs: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let mut results = Vec::<Vec<f32>>::with_capacity(inputs.len());
+        for chunk in inputs.chunks(MAX_BATCH_SIZE) {
+            results.append(&mut self.run_batch(chunk)?);
+        }
+        Ok(results)
     }
 
     async fn encode_size(&self) -> u64 {
-        ENCODE_SIZE
+        self.encode_size
+    }
+
+    fn model_identity(&self) -> String {
+        self.model_identity.to_string()
     }
 }
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{debug, info};
+
+use crate::config::InstructMatcherConfig;
+use crate::core::instruct_matcher::{
+    confidence_threshold, dot, normalize, InstructMatcher, MatchResult, PointPayload,
+    ThresholdPolicy,
+};
+
+/// 平铺存储全部指令点并逐一计算余弦相似度，适合默认指令数量较少的部署
+pub struct ExactCosine {
+    points: Vec<PointPayload>,
+    threshold_policy: ThresholdPolicy,
+}
+
+#[async_trait]
+impl InstructMatcher for ExactCosine {
+    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let confidence_threshold = confidence_threshold(instruct_matcher_config)?;
+        info!("ExactCosine Confidence Threshold: {}", confidence_threshold);
+        Ok(ExactCosine {
+            points: Vec::new(),
+            threshold_policy: ThresholdPolicy::new(instruct_matcher_config, confidence_threshold),
+        })
+    }
+
+    async fn search_top_k(
+        &self,
+        _instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>> {
+        let query = normalize(&point);
+        let mut scored_points = self
+            .points
+            .iter()
+            .map(|point| (point, dot(&query, &point.encode)))
+            .collect::<Vec<(&PointPayload, f32)>>();
+        scored_points.sort_by(|(_, left), (_, right)| right.total_cmp(left));
+        let match_results = scored_points
+            .into_iter()
+            .take(k)
+            .map(|(point, score)| MatchResult {
+                submodule_id: point.submodule_id.to_string(),
+                instruct: point.instruct.to_string(),
+                uuid: point.uuid.to_string(),
+                score,
+            })
+            .collect::<Vec<MatchResult>>();
+        debug!("top {} search result: {:?}", k, &match_results);
+        Ok(match_results)
+    }
+
+    fn is_match(&self, match_result: &MatchResult) -> bool {
+        self.threshold_policy.is_match(match_result)
+    }
+
+    async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        // 存入时即归一化，查询时只需计算点积
+        for mut point in points {
+            point.encode = normalize(&point.encode);
+            self.points.push(point);
+        }
+        Ok(())
+    }
+
+    async fn remove_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        self.points
+            .retain(|x| !points.iter().any(|point| point.uuid.eq(&x.uuid)));
+        Ok(())
+    }
+}
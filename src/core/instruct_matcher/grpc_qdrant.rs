@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::ops::Index;
 use std::string::ToString;
 
 use anyhow::{anyhow, Result};
@@ -14,129 +13,371 @@ use qdrant_client::qdrant::value::Kind::StringValue;
 use qdrant_client::qdrant::vectors_config::Config;
 use qdrant_client::qdrant::with_payload_selector::SelectorOptions::Enable;
 use qdrant_client::qdrant::{
-    CreateCollection, PointId, PointsIdsList, PointsSelector, ScoredPoint, VectorParams,
-    VectorsConfig, WithPayloadSelector,
+    Condition, CountPoints, CreateCollection, Filter, PointId, PointsIdsList, PointsSelector,
+    VectorParams, VectorsConfig, WithPayloadSelector,
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::config::InstructMatcherConfig;
-use crate::core::instruct_matcher::{InstructMatcher, PointPayload};
+use crate::core::instruct_matcher::{
+    confidence_threshold, InstructMatcher, MatchResult, PointPayload, ThresholdPolicy,
+    ENCODE_SIZE_FIELD,
+};
 
 pub const QDRANT_GRPC_ADDR_FIELD: &str = "qdrant_grpc_addr";
-const ENCODE_SIZE_FIELD: &str = "encode_size";
-const COLLECTION_NAME: &str = "instruct";
+pub const COLLECTION_NAME_FIELD: &str = "collection_name";
+pub const DISTANCE_FIELD: &str = "distance";
+pub const CHUNK_SIZE_FIELD: &str = "chunk_size";
+pub const API_KEY_FIELD: &str = "api_key";
+pub const ON_DIMENSION_MISMATCH_FIELD: &str = "on_dimension_mismatch";
+pub const INSTANCE_ID_FIELD: &str = "instance_id";
+/// 为true时启动时删除集合中没有实例标记的旧版本指令点，否则标记为当前实例的指令点
+pub const PURGE_LEGACY_POINTS_FIELD: &str = "purge_legacy_points";
+const DEFAULT_COLLECTION_NAME: &str = "instruct";
 const MODULE_NAME: &str = "module_name";
 const INSTRUCT: &str = "instruct";
-const CHUNK_SIZE: usize = 4;
-const CONFIDENCE_THRESHOLD: f32 = 0.7;
+const INSTANCE_ID: &str = "instance_id";
+const DEFAULT_CHUNK_SIZE: usize = 4;
+
+/// 已存在集合的向量配置与当前配置不一致时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DimensionMismatchPolicy {
+    Refuse,
+    Recreate,
+}
 
+/// 地址使用`https`时自动启用TLS，只检索和清理带有自身`instance_id`标记的指令点，
+/// 未配置时使用主机名，同一主机上共用集合的多个终端需分别配置，
+/// 旧版本写入的没有实例标记的指令点在启动时归入当前实例，仍可被检索
 pub struct GrpcQdrant {
     qdrant_client: QdrantClient,
+    collection_name: String,
+    instance_id: String,
+    distance: Distance,
+    chunk_size: usize,
+    threshold_policy: ThresholdPolicy,
 }
 
-#[async_trait]
-impl InstructMatcher for GrpcQdrant {
-    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
-    where
-        Self: Sized + Send + Sync,
-    {
-        if instruct_matcher_config
-            .config_map
-            .get(ENCODE_SIZE_FIELD)
-            .is_none()
+impl GrpcQdrant {
+    /// 返回已存在集合的向量维度与距离，集合不存在时返回`None`
+    async fn existing_vector_params(&self) -> Result<Option<(u64, i32)>> {
+        let collections = self.qdrant_client.list_collections().await?;
+        if !collections
+            .collections
+            .iter()
+            .any(|collection| collection.name.eq(&self.collection_name))
         {
-            return Err(anyhow!(
-                "Required configuration {:?} is missing",
-                ENCODE_SIZE_FIELD
-            ));
+            return Ok(None);
         }
-        if instruct_matcher_config
-            .config_map
-            .get(QDRANT_GRPC_ADDR_FIELD)
-            .is_none()
+        let collection_info = self
+            .qdrant_client
+            .collection_info(&self.collection_name)
+            .await?;
+        match collection_info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| vectors_config.config)
         {
-            return Err(anyhow!(
-                "Required configuration {:?} is missing",
-                QDRANT_GRPC_ADDR_FIELD
-            ));
-        }
-        let qdrant_client = QdrantClientConfig::from_url(
-            instruct_matcher_config
-                .config_map
-                .get(QDRANT_GRPC_ADDR_FIELD)
-                .unwrap(),
-        )
-        .build()?;
-        let encode_size = instruct_matcher_config
-            .config_map
-            .get(ENCODE_SIZE_FIELD)
-            .unwrap()
-            .parse::<u64>()?;
-
-        let mut collection_created = false;
-        let collections = qdrant_client.list_collections().await?;
-        for collection in collections.collections {
-            if collection.name.eq(COLLECTION_NAME) {
-                // 暂时不判断向量配置等是否相等，等影响体验再进行优化
-                collection_created = true;
-                break;
+            Some(Config::Params(vector_params)) => {
+                Ok(Some((vector_params.size, vector_params.distance)))
             }
+            _ => Err(anyhow!(
+                "Qdrant Collection {:?} Not Use Single Unnamed Vector",
+                &self.collection_name
+            )),
         }
-        if !collection_created {
-            qdrant_client
-                .create_collection(&CreateCollection {
-                    collection_name: COLLECTION_NAME.to_string(),
-                    vectors_config: Some(VectorsConfig {
-                        config: Some(Config::Params(VectorParams {
-                            size: encode_size,
-                            distance: Distance::Cosine.into(),
-                            ..Default::default()
-                        })),
-                    }),
-                    ..Default::default()
-                })
-                .await?;
+    }
+
+    fn instance_condition(&self) -> Condition {
+        Condition::matches(INSTANCE_ID, self.instance_id.to_string())
+    }
+
+    async fn delete_points_by_filter(&self, filter: Filter) -> Result<()> {
+        debug!("delete points by filter: {:?}", &filter);
+        self.qdrant_client
+            .delete_points(&self.collection_name, None, &filter.into(), None)
+            .await?;
+        Ok(())
+    }
+
+    /// 旧版本写入的指令点没有实例标记，默认标记为当前实例，继续参与检索，
+    /// 在子模块重新注册后的下次启动时按本实例的残留指令点清理
+    async fn migrate_legacy_points(&self, purge_legacy_points: bool) -> Result<()> {
+        let legacy_filter = Filter::must([Condition::is_empty(INSTANCE_ID)]);
+        let legacy_count = self
+            .qdrant_client
+            .count(&CountPoints {
+                collection_name: self.collection_name.to_string(),
+                filter: Some(legacy_filter.clone()),
+                exact: Some(true),
+                ..Default::default()
+            })
+            .await?
+            .result
+            .map_or(0, |result| result.count);
+        if legacy_count == 0 {
+            return Ok(());
+        }
+        if purge_legacy_points {
+            info!(
+                "Remove {} Legacy Points Without Instance Id From Qdrant Collection {:?}",
+                legacy_count, &self.collection_name
+            );
+            return self.delete_points_by_filter(legacy_filter).await;
         }
-        Ok(GrpcQdrant { qdrant_client })
+        info!(
+            "Tag {} Legacy Points Without Instance Id In Qdrant Collection {:?} With Instance {:?}",
+            legacy_count, &self.collection_name, &self.instance_id
+        );
+        let mut payload = HashMap::<String, Value>::new();
+        payload.insert(
+            INSTANCE_ID.to_string(),
+            Value {
+                kind: Some(StringValue(self.instance_id.to_string())),
+            },
+        );
+        self.qdrant_client
+            .set_payload(
+                &self.collection_name,
+                None,
+                &legacy_filter.into(),
+                Payload::new_from_hashmap(payload),
+                None,
+            )
+            .await?;
+        Ok(())
     }
 
-    async fn search(&self, point: Vec<f32>) -> Result<String> {
-        let mut search_result = Vec::<ScoredPoint>::new();
-        {
-            let search_req = SearchPoints {
-                collection_name: COLLECTION_NAME.to_string(),
-                vector: point,
-                limit: 1,
-                with_payload: Some(WithPayloadSelector {
-                    selector_options: Some(Enable(true)),
+    async fn create_collection(&self, encode_size: u64) -> Result<()> {
+        self.qdrant_client
+            .create_collection(&CreateCollection {
+                collection_name: self.collection_name.to_string(),
+                vectors_config: Some(VectorsConfig {
+                    config: Some(Config::Params(VectorParams {
+                        size: encode_size,
+                        distance: self.distance.into(),
+                        ..Default::default()
+                    })),
                 }),
                 ..Default::default()
-            };
-            debug!("search instruct request: {:?}", &search_req);
-            let search_resp = self.qdrant_client.search_points(&search_req).await?;
-            debug!("search instruct response: {:?}", &search_resp);
-            search_result.append(search_resp.result.clone().as_mut());
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// 使用主机名作为默认实例标记，获取不到时使用集合名称
+fn default_instance_id(collection_name: &str) -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|hostname| hostname.trim().to_string())
+        })
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| collection_name.to_string())
+}
+
+#[async_trait]
+impl InstructMatcher for GrpcQdrant {
+    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let config_map = &instruct_matcher_config.config_map;
+        let encode_size = match config_map.get(ENCODE_SIZE_FIELD) {
+            None => {
+                return Err(anyhow!(
+                    "Required configuration {:?} is missing",
+                    ENCODE_SIZE_FIELD
+                ))
+            }
+            Some(encode_size) => encode_size.parse::<u64>()?,
+        };
+        let qdrant_client = match config_map.get(QDRANT_GRPC_ADDR_FIELD) {
+            None => {
+                return Err(anyhow!(
+                    "Required configuration {:?} is missing",
+                    QDRANT_GRPC_ADDR_FIELD
+                ))
+            }
+            Some(qdrant_grpc_addr) => QdrantClientConfig::from_url(qdrant_grpc_addr)
+                .with_api_key(
+                    config_map
+                        .get(API_KEY_FIELD)
+                        .map(|api_key| api_key.as_str()),
+                )
+                .build()?,
+        };
+        let collection_name = config_map
+            .get(COLLECTION_NAME_FIELD)
+            .map_or(DEFAULT_COLLECTION_NAME, |name| name.as_str())
+            .to_string();
+        let distance = match config_map.get(DISTANCE_FIELD) {
+            None => Distance::Cosine,
+            Some(distance) => match Distance::from_str_name(distance) {
+                Some(Distance::UnknownDistance) | None => {
+                    return Err(anyhow!(
+                        "Config {:?} Value {:?} Not Support, Expect \"Cosine\", \"Euclid\", \"Dot\" Or \"Manhattan\"",
+                        DISTANCE_FIELD,
+                        distance
+                    ))
+                }
+                Some(distance) => distance,
+            },
+        };
+        let chunk_size = match config_map.get(CHUNK_SIZE_FIELD) {
+            None => DEFAULT_CHUNK_SIZE,
+            Some(chunk_size) => chunk_size.parse::<usize>()?,
+        };
+        let on_dimension_mismatch = match config_map
+            .get(ON_DIMENSION_MISMATCH_FIELD)
+            .map(|policy| policy.as_str())
+        {
+            None | Some("refuse") => DimensionMismatchPolicy::Refuse,
+            Some("recreate") => DimensionMismatchPolicy::Recreate,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"refuse\" Or \"recreate\"",
+                    ON_DIMENSION_MISMATCH_FIELD,
+                    other
+                ))
+            }
+        };
+        let instance_id = match config_map.get(INSTANCE_ID_FIELD) {
+            Some(instance_id) if !instance_id.is_empty() => instance_id.to_string(),
+            _ => {
+                let instance_id = default_instance_id(&collection_name);
+                info!(
+                    "Config {:?} Not Set, Use {:?}",
+                    INSTANCE_ID_FIELD, &instance_id
+                );
+                instance_id
+            }
+        };
+        let purge_legacy_points = match config_map.get(PURGE_LEGACY_POINTS_FIELD) {
+            None => false,
+            Some(purge_legacy_points) => purge_legacy_points.parse::<bool>()?,
+        };
+        let confidence_threshold = confidence_threshold(instruct_matcher_config)?;
+        info!(
+            "GrpcQdrant Collection: {:?}, Instance: {:?}, Distance: {:?}, Confidence Threshold: {}",
+            &collection_name, &instance_id, distance, confidence_threshold
+        );
+        let grpc_qdrant = GrpcQdrant {
+            qdrant_client,
+            collection_name,
+            instance_id,
+            distance,
+            chunk_size,
+            threshold_policy: ThresholdPolicy::new(instruct_matcher_config, confidence_threshold),
+        };
+
+        match grpc_qdrant.existing_vector_params().await? {
+            None => grpc_qdrant.create_collection(encode_size).await?,
+            Some((size, existing_distance))
+                if size == encode_size && existing_distance == i32::from(distance) => {}
+            Some((size, existing_distance)) => match on_dimension_mismatch {
+                DimensionMismatchPolicy::Refuse => {
+                    return Err(anyhow!(
+                        "Qdrant Collection {:?} Vector Size {} Distance {:?} Not Match Config Size {} Distance {:?}",
+                        &grpc_qdrant.collection_name,
+                        size,
+                        Distance::from_i32(existing_distance),
+                        encode_size,
+                        distance
+                    ))
+                }
+                DimensionMismatchPolicy::Recreate => {
+                    warn!(
+                        "Recreate Qdrant Collection {:?}, Vector Size {} To {}",
+                        &grpc_qdrant.collection_name, size, encode_size
+                    );
+                    grpc_qdrant
+                        .qdrant_client
+                        .delete_collection(&grpc_qdrant.collection_name)
+                        .await?;
+                    grpc_qdrant.create_collection(encode_size).await?;
+                }
+            },
         }
-        if !search_result.is_empty() {
-            let best_point = search_result.index(0);
-            debug!("best point score is {}", best_point.score);
-            if best_point.score >= CONFIDENCE_THRESHOLD {
-                let payload = best_point.clone().payload;
-                debug!("best point payload: {:?}", &payload);
-                if let (Some(name_kind), Some(instruct_kind)) =
-                    (payload.get(MODULE_NAME), payload.get(INSTRUCT))
+        // 启动时还没有子模块注册，上次运行残留的本实例指令点全部清理
+        info!(
+            "Remove Stale Points Of Instance {:?}",
+            &grpc_qdrant.instance_id
+        );
+        grpc_qdrant
+            .delete_points_by_filter(Filter::must([grpc_qdrant.instance_condition()]))
+            .await?;
+        grpc_qdrant
+            .migrate_legacy_points(purge_legacy_points)
+            .await?;
+        Ok(grpc_qdrant)
+    }
+
+    async fn search_top_k(
+        &self,
+        _instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>> {
+        let search_req = SearchPoints {
+            collection_name: self.collection_name.to_string(),
+            vector: point,
+            limit: k as u64,
+            filter: Some(Filter::must([self.instance_condition()])),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(Enable(true)),
+            }),
+            ..Default::default()
+        };
+        debug!("search instruct request: {:?}", &search_req);
+        let search_resp = self.qdrant_client.search_points(&search_req).await?;
+        debug!("search instruct response: {:?}", &search_resp);
+        let mut match_results = Vec::<MatchResult>::new();
+        for scored_point in search_resp.result {
+            let payload = &scored_point.payload;
+            if let (Some(name_kind), Some(instruct_kind)) =
+                (payload.get(MODULE_NAME), payload.get(INSTRUCT))
+            {
+                if let (Some(StringValue(module_name)), Some(StringValue(default_instruct))) =
+                    (name_kind.clone().kind, instruct_kind.clone().kind)
                 {
-                    if let (Some(StringValue(module_name)), Some(StringValue(default_instruct))) =
-                        (name_kind.clone().kind, instruct_kind.clone().kind)
-                    {
-                        info!("search result module_name is {:?}", &module_name);
-                        debug!("default_instruct is {:?}", &default_instruct);
-                        return Ok(module_name);
-                    }
+                    let uuid = match scored_point.id.and_then(|id| id.point_id_options) {
+                        Some(PointIdOptions::Uuid(uuid)) => uuid,
+                        Some(PointIdOptions::Num(num)) => num.to_string(),
+                        None => String::new(),
+                    };
+                    match_results.push(MatchResult {
+                        submodule_id: module_name,
+                        instruct: default_instruct,
+                        uuid,
+                        score: scored_point.score,
+                    });
+                    continue;
                 }
             }
+            debug!("skip point with invalid payload: {:?}", payload);
         }
-        return Err(anyhow!("Cannot Search Match Submodule By This Instruct"));
+        Ok(match_results)
+    }
+
+    /// 距离类度量的`score`越小越相似
+    fn is_match(&self, match_result: &MatchResult) -> bool {
+        match self.distance {
+            Distance::Euclid | Distance::Manhattan => {
+                match_result.score <= self.threshold_policy.threshold(match_result)
+            }
+            _ => self.threshold_policy.is_match(match_result),
+        }
+    }
+
+    fn score_is_distance(&self) -> bool {
+        matches!(self.distance, Distance::Euclid | Distance::Manhattan)
     }
 
     async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
@@ -146,6 +387,12 @@ impl InstructMatcher for GrpcQdrant {
         let mut point_structs = Vec::<PointStruct>::new();
         // 创建公共部分负载
         let mut payload = HashMap::<String, Value>::new();
+        payload.insert(
+            INSTANCE_ID.to_string(),
+            Value {
+                kind: Some(StringValue(self.instance_id.to_string())),
+            },
+        );
         payload.insert(
             MODULE_NAME.to_string(),
             Value {
@@ -168,7 +415,13 @@ impl InstructMatcher for GrpcQdrant {
             ));
         }
         self.qdrant_client
-            .upsert_points_batch_blocking(COLLECTION_NAME, None, point_structs, None, CHUNK_SIZE)
+            .upsert_points_batch_blocking(
+                &self.collection_name,
+                None,
+                point_structs,
+                None,
+                self.chunk_size,
+            )
             .await?;
         Ok(())
     }
@@ -185,7 +438,7 @@ impl InstructMatcher for GrpcQdrant {
         }
         self.qdrant_client
             .delete_points(
-                COLLECTION_NAME,
+                &self.collection_name,
                 None,
                 &PointsSelector {
                     points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
@@ -197,4 +450,14 @@ impl InstructMatcher for GrpcQdrant {
             .await?;
         Ok(())
     }
+
+    async fn retain_submodules(&mut self, submodule_ids: &[String]) -> Result<()> {
+        let mut filter = Filter::must([self.instance_condition()]);
+        if !submodule_ids.is_empty() {
+            filter
+                .must_not
+                .push(Condition::matches(MODULE_NAME, submodule_ids.to_vec()));
+        }
+        self.delete_points_by_filter(filter).await
+    }
 }
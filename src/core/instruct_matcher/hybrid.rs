@@ -0,0 +1,522 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tracing::{debug, info};
+
+use crate::config::InstructMatcherConfig;
+use crate::core::instruct_matcher::{
+    create_inner_instruct_matcher, InstructMatcher, MatchResult, PointPayload, ThresholdPolicy,
+};
+
+pub const FUSION_TYPE_FIELD: &str = "fusion_type";
+pub const VECTOR_WEIGHT_FIELD: &str = "vector_weight";
+pub const LEXICAL_WEIGHT_FIELD: &str = "lexical_weight";
+pub const RRF_K_FIELD: &str = "rrf_k";
+pub const FUSION_THRESHOLD_FIELD: &str = "fusion_threshold";
+pub const MIN_LEXICAL_COVERAGE_FIELD: &str = "min_lexical_coverage";
+const DEFAULT_VECTOR_WEIGHT: f32 = 0.5;
+const DEFAULT_LEXICAL_WEIGHT: f32 = 0.5;
+const DEFAULT_RRF_K: f32 = 60.0;
+const DEFAULT_FUSION_THRESHOLD: f32 = 0.5;
+const DEFAULT_MIN_LEXICAL_COVERAGE: f32 = 0.5;
+/// 每路检索的最少候选数量，候选越多融合结果越稳定
+const MIN_CANDIDATE_SIZE: usize = 20;
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FusionType {
+    /// 倒数排名融合，分数归一化为两路均排第一时为1
+    Rrf,
+    /// 向量相似度与词法覆盖率加权求和
+    Weighted,
+}
+
+/// 中文按字切分并补充相邻字组成的二元组，其余连续的字母数字作为一个词
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::<String>::new();
+    let mut word = String::new();
+    let mut previous_cjk: Option<char> = None;
+    for c in text.to_lowercase().chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push(c.to_string());
+            if let Some(previous) = previous_cjk {
+                tokens.push(format!("{}{}", previous, c));
+            }
+            previous_cjk = Some(c);
+            continue;
+        }
+        previous_cjk = None;
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{4e00}'..='\u{9fff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{3040}'..='\u{30ff}'
+        | '\u{ac00}'..='\u{d7af}')
+}
+
+struct LexicalDocument {
+    point: PointPayload,
+    term_freqs: HashMap<String, usize>,
+    len: usize,
+}
+
+struct LexicalHit<'a> {
+    point: &'a PointPayload,
+    score: f32,
+    coverage: f32,
+}
+
+/// 基于指令文本的BM25倒排索引
+#[derive(Default)]
+struct Bm25Index {
+    documents: HashMap<String, LexicalDocument>,
+    doc_freqs: HashMap<String, usize>,
+    total_len: usize,
+}
+
+impl Bm25Index {
+    fn insert(&mut self, point: &PointPayload) {
+        if self.documents.contains_key(&point.uuid) {
+            return;
+        }
+        let tokens = tokenize(&point.instruct);
+        let mut term_freqs = HashMap::<String, usize>::new();
+        for token in tokens.iter() {
+            *term_freqs.entry(token.to_string()).or_default() += 1;
+        }
+        for term in term_freqs.keys() {
+            *self.doc_freqs.entry(term.to_string()).or_default() += 1;
+        }
+        self.total_len += tokens.len();
+        // 词法索引只需要指令文本，不保存编码
+        self.documents.insert(
+            point.uuid.to_string(),
+            LexicalDocument {
+                point: PointPayload {
+                    encode: Vec::new(),
+                    ..point.clone()
+                },
+                term_freqs,
+                len: tokens.len(),
+            },
+        );
+    }
+
+    fn remove(&mut self, uuid: &str) {
+        if let Some(document) = self.documents.remove(uuid) {
+            for term in document.term_freqs.keys() {
+                if let Some(doc_freq) = self.doc_freqs.get_mut(term) {
+                    *doc_freq -= 1;
+                    if *doc_freq == 0 {
+                        self.doc_freqs.remove(term);
+                    }
+                }
+            }
+            self.total_len -= document.len;
+        }
+    }
+
+    fn idf(&self, term: &str) -> f32 {
+        let doc_count = self.documents.len() as f32;
+        let doc_freq = self.doc_freqs.get(term).copied().unwrap_or_default() as f32;
+        ((doc_count - doc_freq + 0.5) / (doc_freq + 0.5) + 1.0).ln()
+    }
+
+    /// 返回BM25分数最高的`k`个文档，不包含分数为0的文档，
+    /// 同时返回文档覆盖的查询词按IDF加权的比例，常用字只占很小的比例
+    fn search(&self, text: &str, k: usize) -> Vec<LexicalHit<'_>> {
+        if self.documents.is_empty() {
+            return Vec::new();
+        }
+        let query_terms = tokenize(text);
+        let unique_terms = query_terms.iter().collect::<HashSet<&String>>();
+        let total_idf = unique_terms.iter().map(|term| self.idf(term)).sum::<f32>();
+        let avg_len = self.total_len as f32 / self.documents.len() as f32;
+        let mut lexical_hits = Vec::<LexicalHit>::new();
+        for document in self.documents.values() {
+            let mut score = 0.0f32;
+            for term in query_terms.iter() {
+                if let Some(term_freq) = document.term_freqs.get(term) {
+                    let term_freq = *term_freq as f32;
+                    score += self.idf(term) * term_freq * (BM25_K1 + 1.0)
+                        / (term_freq
+                            + BM25_K1 * (1.0 - BM25_B + BM25_B * document.len as f32 / avg_len));
+                }
+            }
+            if score > 0.0 {
+                let covered_idf = unique_terms
+                    .iter()
+                    .filter(|term| document.term_freqs.contains_key(term.as_str()))
+                    .map(|term| self.idf(term))
+                    .sum::<f32>();
+                lexical_hits.push(LexicalHit {
+                    point: &document.point,
+                    score,
+                    coverage: covered_idf / total_idf,
+                });
+            }
+        }
+        lexical_hits.sort_by(|left, right| right.score.total_cmp(&left.score));
+        lexical_hits.truncate(k);
+        lexical_hits
+    }
+}
+
+fn parse_f32(
+    instruct_matcher_config: &InstructMatcherConfig,
+    field: &str,
+    default: f32,
+) -> Result<f32> {
+    match instruct_matcher_config.config_map.get(field) {
+        None => Ok(default),
+        Some(value) => Ok(value.parse::<f32>()?),
+    }
+}
+
+/// 在内部向量匹配器之外维护指令文本的BM25索引，两路检索结果按配置的方式融合，
+/// 融合后的`score`范围为0到1，子模块及指令阈值同样作用于融合后的分数，
+/// 只有内部匹配器判定命中或词法覆盖率达到下限的候选才会被视为匹配
+pub struct HybridMatcher {
+    fusion_type: FusionType,
+    vector_weight: f32,
+    lexical_weight: f32,
+    rrf_k: f32,
+    min_lexical_coverage: f32,
+    threshold_policy: ThresholdPolicy,
+    lexical_index: Bm25Index,
+    /// 最近一次检索中被任一路检索确认的候选，调用方在同一次加锁内检索并判断是否匹配
+    confirmed_uuids: Mutex<HashSet<String>>,
+    inner_matcher: Box<dyn InstructMatcher + Send + Sync>,
+}
+
+#[async_trait]
+impl InstructMatcher for HybridMatcher {
+    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let fusion_type = match instruct_matcher_config
+            .config_map
+            .get(FUSION_TYPE_FIELD)
+            .map(|fusion_type| fusion_type.as_str())
+        {
+            None | Some("rrf") => FusionType::Rrf,
+            Some("weighted") => FusionType::Weighted,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"rrf\" Or \"weighted\"",
+                    FUSION_TYPE_FIELD,
+                    other
+                ))
+            }
+        };
+        let vector_weight = parse_f32(
+            instruct_matcher_config,
+            VECTOR_WEIGHT_FIELD,
+            DEFAULT_VECTOR_WEIGHT,
+        )?;
+        let lexical_weight = parse_f32(
+            instruct_matcher_config,
+            LEXICAL_WEIGHT_FIELD,
+            DEFAULT_LEXICAL_WEIGHT,
+        )?;
+        if vector_weight < 0.0 || lexical_weight < 0.0 || vector_weight + lexical_weight <= 0.0 {
+            return Err(anyhow!(
+                "Config {:?} And {:?} Must Be Non-Negative And Not Both Zero",
+                VECTOR_WEIGHT_FIELD,
+                LEXICAL_WEIGHT_FIELD
+            ));
+        }
+        let rrf_k = parse_f32(instruct_matcher_config, RRF_K_FIELD, DEFAULT_RRF_K)?;
+        let fusion_threshold = parse_f32(
+            instruct_matcher_config,
+            FUSION_THRESHOLD_FIELD,
+            DEFAULT_FUSION_THRESHOLD,
+        )?;
+        let min_lexical_coverage = parse_f32(
+            instruct_matcher_config,
+            MIN_LEXICAL_COVERAGE_FIELD,
+            DEFAULT_MIN_LEXICAL_COVERAGE,
+        )?;
+        let inner_matcher = create_inner_instruct_matcher(instruct_matcher_config).await?;
+        // 加权融合直接使用向量相似度，距离类度量无法与BM25分数相加
+        if fusion_type == FusionType::Weighted && inner_matcher.score_is_distance() {
+            return Err(anyhow!(
+                "Config {:?} Value \"weighted\" Not Support Distance Metric Of Inner Matcher",
+                FUSION_TYPE_FIELD
+            ));
+        }
+        info!(
+            "Hybrid Matcher Fusion Type: {:?}, Vector Weight: {}, Lexical Weight: {}, Fusion Threshold: {}",
+            fusion_type, vector_weight, lexical_weight, fusion_threshold
+        );
+        Ok(HybridMatcher {
+            fusion_type,
+            vector_weight,
+            lexical_weight,
+            rrf_k,
+            min_lexical_coverage,
+            threshold_policy: ThresholdPolicy::new(instruct_matcher_config, fusion_threshold),
+            lexical_index: Bm25Index::default(),
+            confirmed_uuids: Mutex::new(HashSet::new()),
+            inner_matcher,
+        })
+    }
+
+    async fn search_top_k(
+        &self,
+        instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>> {
+        let candidate_size = k.max(MIN_CANDIDATE_SIZE);
+        let vector_results = self
+            .inner_matcher
+            .search_top_k(instruct, point, candidate_size)
+            .await?;
+        let lexical_hits = self.lexical_index.search(instruct, candidate_size);
+        let weight_sum = self.vector_weight + self.lexical_weight;
+
+        let mut fused = HashMap::<String, (MatchResult, bool)>::new();
+        for (rank, vector_result) in vector_results.into_iter().enumerate() {
+            let score = match self.fusion_type {
+                FusionType::Rrf => self.vector_weight / (self.rrf_k + rank as f32 + 1.0),
+                FusionType::Weighted => self.vector_weight * vector_result.score.max(0.0),
+            };
+            let confirmed = self.inner_matcher.is_match(&vector_result);
+            fused.insert(
+                vector_result.uuid.to_string(),
+                (
+                    MatchResult {
+                        score,
+                        ..vector_result
+                    },
+                    confirmed,
+                ),
+            );
+        }
+        for (rank, lexical_hit) in lexical_hits.into_iter().enumerate() {
+            let score = match self.fusion_type {
+                FusionType::Rrf => self.lexical_weight / (self.rrf_k + rank as f32 + 1.0),
+                FusionType::Weighted => self.lexical_weight * lexical_hit.coverage,
+            };
+            let (match_result, confirmed) = fused
+                .entry(lexical_hit.point.uuid.to_string())
+                .or_insert_with(|| {
+                    (
+                        MatchResult {
+                            submodule_id: lexical_hit.point.submodule_id.to_string(),
+                            instruct: lexical_hit.point.instruct.to_string(),
+                            uuid: lexical_hit.point.uuid.to_string(),
+                            score: 0.0,
+                        },
+                        false,
+                    )
+                });
+            match_result.score += score;
+            *confirmed |= lexical_hit.coverage >= self.min_lexical_coverage;
+        }
+        // 归一化到0到1之间，便于配置统一的融合阈值
+        let max_score = match self.fusion_type {
+            FusionType::Rrf => weight_sum / (self.rrf_k + 1.0),
+            FusionType::Weighted => weight_sum,
+        };
+        let mut fused_results = fused
+            .into_values()
+            .map(|(match_result, confirmed)| {
+                (
+                    MatchResult {
+                        score: match_result.score / max_score,
+                        ..match_result
+                    },
+                    confirmed,
+                )
+            })
+            .collect::<Vec<(MatchResult, bool)>>();
+        fused_results.sort_by(|(left, _), (right, _)| right.score.total_cmp(&left.score));
+        fused_results.truncate(k);
+        *self.confirmed_uuids.lock().unwrap() = fused_results
+            .iter()
+            .filter(|(_, confirmed)| *confirmed)
+            .map(|(match_result, _)| match_result.uuid.to_string())
+            .collect();
+        let match_results = fused_results
+            .into_iter()
+            .map(|(match_result, _)| match_result)
+            .collect::<Vec<MatchResult>>();
+        debug!("hybrid top {} search result: {:?}", k, &match_results);
+        Ok(match_results)
+    }
+
+    /// 排名融合时任一候选都有分数，需同时被最近一次检索确认
+    fn is_match(&self, match_result: &MatchResult) -> bool {
+        self.confirmed_uuids
+            .lock()
+            .unwrap()
+            .contains(&match_result.uuid)
+            && self.threshold_policy.is_match(match_result)
+    }
+
+    async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        for point in points.iter() {
+            self.lexical_index.insert(point);
+        }
+        self.inner_matcher.append_points(points).await
+    }
+
+    async fn remove_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        for point in points.iter() {
+            self.lexical_index.remove(&point.uuid);
+        }
+        self.inner_matcher.remove_points(points).await
+    }
+
+    /// 内部匹配器持久化的点重新注册时不会再次插入，在此补充到词法索引
+    async fn get_points(&mut self, submodule_id: &str) -> Result<Vec<PointPayload>> {
+        let points = self.inner_matcher.get_points(submodule_id).await?;
+        for point in points.iter() {
+            self.lexical_index.insert(point);
+        }
+        Ok(points)
+    }
+
+    async fn retain_submodules(&mut self, submodule_ids: &[String]) -> Result<()> {
+        let stale_uuids = self
+            .lexical_index
+            .documents
+            .values()
+            .filter(|document| !submodule_ids.contains(&document.point.submodule_id))
+            .map(|document| document.point.uuid.to_string())
+            .collect::<Vec<String>>();
+        for uuid in stale_uuids {
+            self.lexical_index.remove(&uuid);
+        }
+        self.inner_matcher.retain_submodules(submodule_ids).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::instruct_matcher::INNER_MATCHER_TYPE_FIELD;
+
+    use super::*;
+
+    fn point(uuid: &str, instruct: &str, encode: Vec<f32>) -> PointPayload {
+        PointPayload {
+            encode,
+            submodule_id: "submodule".to_string(),
+            instruct: instruct.to_string(),
+            uuid: uuid.to_string(),
+        }
+    }
+
+    async fn hybrid_matcher(fusion_type: &str) -> HybridMatcher {
+        let mut config_map = HashMap::<String, String>::new();
+        config_map.insert(
+            INNER_MATCHER_TYPE_FIELD.to_string(),
+            "ExactCosine".to_string(),
+        );
+        config_map.insert(FUSION_TYPE_FIELD.to_string(), fusion_type.to_string());
+        let mut hybrid_matcher = HybridMatcher::init(&InstructMatcherConfig {
+            config_map,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        hybrid_matcher
+            .append_points(vec![
+                point("light", "打开客厅的灯", vec![1.0, 0.0]),
+                point("music", "播放音乐", vec![0.0, 1.0]),
+            ])
+            .await
+            .unwrap();
+        hybrid_matcher
+    }
+
+    #[test]
+    fn tokenize_adds_cjk_bigrams() {
+        assert_eq!(
+            tokenize("开灯 Hello, World2"),
+            vec!["开", "灯", "开灯", "hello", "world2"]
+        );
+    }
+
+    #[test]
+    fn bm25_ranks_documents_with_query_terms() {
+        let mut lexical_index = Bm25Index::default();
+        lexical_index.insert(&point("light", "打开客厅的灯", Vec::new()));
+        lexical_index.insert(&point("music", "播放音乐", Vec::new()));
+        lexical_index.insert(&point("bedroom", "打开卧室的灯", Vec::new()));
+        let results = lexical_index.search("客厅的灯", 10);
+        assert_eq!(results[0].point.uuid, "light");
+        assert!((results[0].coverage - 1.0).abs() < 1e-6);
+        assert!(results.iter().all(|hit| hit.point.uuid != "music"));
+
+        let results = lexical_index.search("今天的天气", 10);
+        assert!(results.iter().all(|hit| hit.coverage < 0.5));
+
+        lexical_index.remove("light");
+        let results = lexical_index.search("客厅", 10);
+        assert!(results.is_empty());
+        assert!(!lexical_index.doc_freqs.contains_key("客厅"));
+    }
+
+    #[tokio::test]
+    async fn rrf_fusion_ranks_hits_from_both_retrievals_first() {
+        let hybrid_matcher = hybrid_matcher("rrf").await;
+        let match_results = hybrid_matcher
+            .search_top_k("打开客厅的灯", vec![1.0, 0.0], 2)
+            .await
+            .unwrap();
+        assert_eq!(match_results[0].uuid, "light");
+        assert!((match_results[0].score - 1.0).abs() < 1e-6);
+        assert!(hybrid_matcher.is_match(&match_results[0]));
+    }
+
+    #[tokio::test]
+    async fn unrelated_instruct_does_not_match() {
+        for fusion_type in ["rrf", "weighted"] {
+            let hybrid_matcher = hybrid_matcher(fusion_type).await;
+            let match_results = hybrid_matcher
+                .search_top_k("今天天气怎么样", vec![0.3, -1.0], 2)
+                .await
+                .unwrap();
+            assert!(!match_results.is_empty());
+            assert!(match_results
+                .iter()
+                .all(|match_result| !hybrid_matcher.is_match(match_result)));
+        }
+    }
+
+    #[tokio::test]
+    async fn single_common_character_does_not_match() {
+        for fusion_type in ["rrf", "weighted"] {
+            let hybrid_matcher = hybrid_matcher(fusion_type).await;
+            let match_results = hybrid_matcher
+                .search_top_k("今天的天气", vec![0.3, -1.0], 2)
+                .await
+                .unwrap();
+            assert_eq!(match_results[0].uuid, "light");
+            assert!(!hybrid_matcher.is_match(&match_results[0]));
+        }
+    }
+}
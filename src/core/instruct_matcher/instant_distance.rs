@@ -1,9 +1,36 @@
+use std::collections::HashSet;
+use std::fs::{read_to_string, rename, write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use instant_distance::{Builder, HnswMap, Point, Search};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tracing::{debug, error, info, warn};
 
 use crate::config::InstructMatcherConfig;
-use crate::core::instruct_matcher::{InstructMatcher, PointPayload};
+use crate::core::instruct_matcher::{
+    confidence_threshold, cosine_similarity, normalize, InstructMatcher, MatchResult, PointPayload,
+    ThresholdPolicy, ENCODE_SIZE_FIELD, MODEL_IDENTITY_FIELD,
+};
+
+pub const MAX_DISTANCE_FIELD: &str = "max_distance";
+pub const COMPACTION_THRESHOLD_FIELD: &str = "compaction_threshold";
+pub const INDEX_PATH_FIELD: &str = "index_path";
+const DEFAULT_COMPACTION_THRESHOLD: usize = 256;
+/// instant_distance默认的检索候选数量
+const DEFAULT_EF_SEARCH: usize = 100;
+
+/// 索引快照，`encode_size`与`model_identity`用于加载时校验编码是否由当前编码器生成
+#[derive(Deserialize, Serialize, Default)]
+struct IndexSnapshot {
+    encode_size: usize,
+    #[serde(default)]
+    model_identity: String,
+    points: Vec<PointPayload>,
+}
 
 impl Point for PointPayload {
     fn distance(&self, other: &Self) -> f32 {
@@ -15,60 +42,369 @@ impl Point for PointPayload {
     }
 }
 
+/// 新增的点先放入待合并列表逐一比较，删除的点只记录墓碑，
+/// 两者累计达到`compaction_threshold`时才重建索引，
+/// 存入及检索的编码均经过归一化，欧式距离的排序与余弦相似度一致，
+/// `max_distance`同样作用于归一化后的编码，相当于余弦相似度不低于`1 - max_distance² / 2`
 pub struct InstantDistance {
     hnsw_map: HnswMap<PointPayload, bool>,
+    pending_points: Vec<PointPayload>,
+    tombstones: HashSet<String>,
+    threshold_policy: ThresholdPolicy,
+    max_distance: Option<f32>,
+    compaction_threshold: usize,
+    index_path: Option<String>,
+    model_identity: String,
+    /// 快照版本号，后台写入时只保留最新版本，避免较旧的快照覆盖较新的快照
+    snapshot_version: u64,
+    saved_version: Arc<Mutex<u64>>,
+}
+
+/// 返回编码归一化后的指令点
+fn normalize_point(point: PointPayload) -> PointPayload {
+    PointPayload {
+        encode: normalize(&point.encode),
+        ..point
+    }
+}
+
+/// 读取快照文件，维度或模型标识不一致时丢弃快照，由子模块重新注册补全指令点，
+/// 旧版本保存的未归一化的编码在加载时归一化
+fn load_snapshot(
+    index_path: &str,
+    encode_size: Option<usize>,
+    model_identity: &str,
+) -> Result<Vec<PointPayload>> {
+    if !Path::new(index_path).exists() {
+        return Ok(Vec::new());
+    }
+    let snapshot = serde_json::from_str::<IndexSnapshot>(&read_to_string(index_path)?)?;
+    if !model_identity.is_empty()
+        && !snapshot.points.is_empty()
+        && snapshot.model_identity != model_identity
+    {
+        warn!(
+            "InstantDistance Index {:?} Model Identity {:?} Not Match Encoder {:?}, Discard It",
+            index_path, &snapshot.model_identity, model_identity
+        );
+        return Ok(Vec::new());
+    }
+    if let Some(encode_size) = encode_size {
+        if !snapshot.points.is_empty() && snapshot.encode_size != encode_size {
+            warn!(
+                "InstantDistance Index {:?} Encode Size {} Not Match Config {}, Discard It",
+                index_path, snapshot.encode_size, encode_size
+            );
+            return Ok(Vec::new());
+        }
+    }
+    if let Some(point) = snapshot
+        .points
+        .iter()
+        .find(|point| point.encode.len() != snapshot.encode_size)
+    {
+        warn!(
+            "InstantDistance Index {:?} Point {:?} Encode Size {} Not Match {}, Discard It",
+            index_path,
+            &point.uuid,
+            point.encode.len(),
+            snapshot.encode_size
+        );
+        return Ok(Vec::new());
+    }
+    info!(
+        "Load {} Points From InstantDistance Index {:?}",
+        snapshot.points.len(),
+        index_path
+    );
+    Ok(snapshot.points.into_iter().map(normalize_point).collect())
+}
+
+impl InstantDistance {
+    /// 索引中未被删除的点与待合并的点
+    fn live_points(&self) -> impl Iterator<Item = &PointPayload> {
+        self.hnsw_map
+            .iter()
+            .map(|(_, point)| point)
+            .filter(|point| !self.tombstones.contains(&point.uuid))
+            .chain(self.pending_points.iter())
+    }
+
+    /// 复制当前的点后在阻塞线程池中写入快照，不阻塞持有匹配器锁的线程，
+    /// 先写入临时文件再重命名，避免写入中断导致快照损坏
+    fn save_snapshot(&mut self) {
+        let index_path = match &self.index_path {
+            None => return,
+            Some(index_path) => index_path.to_string(),
+        };
+        self.snapshot_version += 1;
+        let snapshot_version = self.snapshot_version;
+        let points = self.live_points().cloned().collect::<Vec<PointPayload>>();
+        let snapshot = IndexSnapshot {
+            encode_size: points.first().map_or(0, |point| point.encode.len()),
+            model_identity: self.model_identity.to_string(),
+            points,
+        };
+        let saved_version = self.saved_version.clone();
+        spawn_blocking(move || {
+            if let Err(e) = write_snapshot(&index_path, &snapshot, snapshot_version, &saved_version)
+            {
+                error!("Save InstantDistance Index {:?} Error: {}", &index_path, e);
+            }
+        });
+    }
+
+    fn compact_if_needed(&mut self) {
+        if self.pending_points.len() + self.tombstones.len() < self.compaction_threshold {
+            return;
+        }
+        let points = self.live_points().cloned().collect::<Vec<PointPayload>>();
+        self.pending_points.clear();
+        debug!("Compact InstantDistance Index With {} Points", points.len());
+        self.hnsw_map = build_index(points, self.compaction_threshold);
+        self.tombstones.clear();
+    }
+}
+
+fn write_snapshot(
+    index_path: &str,
+    snapshot: &IndexSnapshot,
+    snapshot_version: u64,
+    saved_version: &Mutex<u64>,
+) -> Result<()> {
+    let mut saved_version = saved_version
+        .lock()
+        .map_err(|e| anyhow!("InstantDistance Snapshot Lock Error: {}", e))?;
+    if *saved_version >= snapshot_version {
+        return Ok(());
+    }
+    let tmp_path = format!("{}.tmp", index_path);
+    write(&tmp_path, serde_json::to_string(snapshot)?)?;
+    rename(&tmp_path, index_path)?;
+    *saved_version = snapshot_version;
+    debug!(
+        "Save {} Points To InstantDistance Index {:?}",
+        snapshot.points.len(),
+        index_path
+    );
+    Ok(())
+}
+
+/// 墓碑数量不会超过`compaction_threshold`，检索候选数量按此放大，保证剔除墓碑后仍有足够的结果
+fn build_index(
+    points: Vec<PointPayload>,
+    compaction_threshold: usize,
+) -> HnswMap<PointPayload, bool> {
+    let len = points.len();
+    Builder::default()
+        .ef_search(DEFAULT_EF_SEARCH + compaction_threshold)
+        .build(points, vec![false; len])
 }
 
 #[async_trait]
 impl InstructMatcher for InstantDistance {
-    async fn init(_instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
+    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
     where
         Self: Sized + Send + Sync,
     {
+        let confidence_threshold = confidence_threshold(instruct_matcher_config)?;
+        let max_distance = match instruct_matcher_config.config_map.get(MAX_DISTANCE_FIELD) {
+            None => None,
+            Some(max_distance) => Some(max_distance.parse::<f32>()?),
+        };
+        let compaction_threshold = match instruct_matcher_config
+            .config_map
+            .get(COMPACTION_THRESHOLD_FIELD)
+        {
+            None => DEFAULT_COMPACTION_THRESHOLD,
+            Some(compaction_threshold) => compaction_threshold.parse::<usize>()?,
+        };
+        info!(
+            "InstantDistance Confidence Threshold: {}, Max Distance: {:?}, Compaction Threshold: {}",
+            confidence_threshold, max_distance, compaction_threshold
+        );
+        let index_path = instruct_matcher_config
+            .config_map
+            .get(INDEX_PATH_FIELD)
+            .map(|index_path| index_path.to_string());
+        let model_identity = instruct_matcher_config
+            .config_map
+            .get(MODEL_IDENTITY_FIELD)
+            .map_or(String::new(), |model_identity| model_identity.to_string());
+        let points = match &index_path {
+            None => Vec::new(),
+            Some(index_path) => {
+                let encode_size = match instruct_matcher_config.config_map.get(ENCODE_SIZE_FIELD) {
+                    None => None,
+                    Some(encode_size) => Some(encode_size.parse::<usize>()?),
+                };
+                load_snapshot(index_path, encode_size, &model_identity)?
+            }
+        };
         Ok(InstantDistance {
-            hnsw_map: Builder::default().build(vec![], vec![]),
+            hnsw_map: build_index(points, compaction_threshold),
+            pending_points: Vec::new(),
+            tombstones: HashSet::new(),
+            threshold_policy: ThresholdPolicy::new(instruct_matcher_config, confidence_threshold),
+            max_distance,
+            compaction_threshold,
+            index_path,
+            model_identity,
+            snapshot_version: 0,
+            saved_version: Arc::new(Mutex::new(0)),
         })
     }
 
-    async fn search(&self, point: Vec<f32>) -> Result<String> {
-        match self
+    /// 配置了`max_distance`时，超出该距离的指令点不会返回
+    async fn search_top_k(
+        &self,
+        _instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>> {
+        let query = PointPayload {
+            encode: normalize(&point),
+            ..Default::default()
+        };
+        let mut search = Search::default();
+        // 被删除的点仍在索引中，按墓碑数量多取候选，剔除后再截取前`k`个
+        let base_points = self
             .hnsw_map
-            .search(
-                &PointPayload {
-                    encode: point,
-                    ..Default::default()
-                },
-                &mut Search::default(),
-            )
-            .next()
-        {
-            None => Err(anyhow!("Not Search Result")),
-            Some(item) => Ok(item.point.submodule_id.clone()),
+            .search(&query, &mut search)
+            .take(k + self.tombstones.len())
+            .filter(|item| !self.tombstones.contains(&item.point.uuid))
+            .take(k)
+            .map(|item| (item.point, item.distance));
+        let pending_points = self
+            .pending_points
+            .iter()
+            .map(|point| (point, query.distance(point)));
+        let mut match_results = Vec::<MatchResult>::new();
+        for (point, distance) in base_points.chain(pending_points) {
+            if let Some(max_distance) = self.max_distance {
+                if distance > max_distance {
+                    debug!(
+                        "point {:?} distance {} over max distance",
+                        &point.uuid, distance
+                    );
+                    continue;
+                }
+            }
+            match_results.push(MatchResult {
+                submodule_id: point.submodule_id.to_string(),
+                instruct: point.instruct.to_string(),
+                uuid: point.uuid.to_string(),
+                score: cosine_similarity(&query.encode, &point.encode),
+            });
         }
+        // 索引中的候选与待合并列表中的点需一并按相似度重新排序
+        match_results.sort_by(|left, right| right.score.total_cmp(&left.score));
+        match_results.truncate(k);
+        debug!("top {} search result: {:?}", k, &match_results);
+        Ok(match_results)
     }
 
-    async fn append_points(&mut self, mut points: Vec<PointPayload>) -> Result<()> {
-        points.append(
-            &mut self
-                .hnsw_map
-                .iter()
-                .map(|(_, point)| point.clone())
-                .collect(),
-        );
-        let len = points.len();
-        self.hnsw_map = Builder::default().build(points, vec![false; len]);
+    fn is_match(&self, match_result: &MatchResult) -> bool {
+        self.threshold_policy.is_match(match_result)
+    }
+
+    async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        self.pending_points
+            .extend(points.into_iter().map(normalize_point));
+        self.compact_if_needed();
+        self.save_snapshot();
         Ok(())
     }
 
     async fn remove_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
-        let tmp: Vec<PointPayload> = self
+        for point in points {
+            let pending_len = self.pending_points.len();
+            self.pending_points
+                .retain(|pending_point| !pending_point.uuid.eq(&point.uuid));
+            // 不在待合并列表中的点位于索引内，记录墓碑等待重建时剔除
+            if self.pending_points.len() == pending_len {
+                self.tombstones.insert(point.uuid);
+            }
+        }
+        self.compact_if_needed();
+        self.save_snapshot();
+        Ok(())
+    }
+
+    /// 快照中可能残留已不再注册的子模块的指令点，启动后按当前子模块清理
+    async fn retain_submodules(&mut self, submodule_ids: &[String]) -> Result<()> {
+        let pending_len = self.pending_points.len();
+        self.pending_points
+            .retain(|point| submodule_ids.contains(&point.submodule_id));
+        let stale_uuids = self
             .hnsw_map
             .iter()
-            .map(|(_, point)| point.clone())
-            .filter(|x| !points.contains(&x))
-            .collect();
-        let len = tmp.len();
-        self.hnsw_map = Builder::default().build(tmp, vec![false; len]);
+            .map(|(_, point)| point)
+            .filter(|point| {
+                !submodule_ids.contains(&point.submodule_id)
+                    && !self.tombstones.contains(&point.uuid)
+            })
+            .map(|point| point.uuid.to_string())
+            .collect::<Vec<String>>();
+        let removed = pending_len - self.pending_points.len() + stale_uuids.len();
+        if removed == 0 {
+            return Ok(());
+        }
+        info!("Remove {} Stale Points From InstantDistance Index", removed);
+        self.tombstones.extend(stale_uuids);
+        self.compact_if_needed();
+        self.save_snapshot();
         Ok(())
     }
+
+    async fn get_points(&mut self, submodule_id: &str) -> Result<Vec<PointPayload>> {
+        Ok(self
+            .live_points()
+            .filter(|point| point.submodule_id.eq(submodule_id))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn point(uuid: &str, encode: Vec<f32>) -> PointPayload {
+        PointPayload {
+            encode,
+            submodule_id: "submodule".to_string(),
+            instruct: uuid.to_string(),
+            uuid: uuid.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn index_search_ranks_by_cosine_similarity() {
+        let mut config_map = HashMap::<String, String>::new();
+        config_map.insert(COMPACTION_THRESHOLD_FIELD.to_string(), "1".to_string());
+        let mut instant_distance = InstantDistance::init(&InstructMatcherConfig {
+            config_map,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        // 未归一化时`short`与查询的欧式距离更近，但`long`的方向与查询更接近
+        instant_distance
+            .append_points(vec![
+                point("long", vec![10.0, 0.0]),
+                point("short", vec![0.5, 0.5]),
+            ])
+            .await
+            .unwrap();
+        assert!(instant_distance.pending_points.is_empty());
+        let match_results = instant_distance
+            .search_top_k("", vec![1.0, 0.1], 1)
+            .await
+            .unwrap();
+        assert_eq!(match_results[0].uuid, "long");
+        assert!(instant_distance.is_match(&match_results[0]));
+    }
 }
@@ -1,14 +1,30 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
-use crate::config::InstructMatcherConfig;
+use crate::config::{InstructMatcherConfig, InstructMatcherType};
+use crate::core::instruct_matcher::exact_cosine::ExactCosine;
+use crate::core::instruct_matcher::grpc_qdrant::GrpcQdrant;
+use crate::core::instruct_matcher::hybrid::HybridMatcher;
+use crate::core::instruct_matcher::instant_distance::InstantDistance;
+use crate::core::instruct_matcher::rule::RuleMatcher;
 
+pub mod exact_cosine;
 pub mod grpc_qdrant;
+pub mod hybrid;
 pub mod instant_distance;
+pub mod rule;
 
 pub const ENCODE_SIZE_FIELD: &str = "encode_size";
+/// 编码器的模型标识，由启动流程写入，持久化指令点的匹配器据此判断已有编码是否可复用
+pub const MODEL_IDENTITY_FIELD: &str = "model_identity";
+pub const CONFIDENCE_THRESHOLD_FIELD: &str = "confidence_threshold";
+pub const DEFAULT_CONFIDENCE_THRESHOLD: f32 = 0.7;
+pub const INNER_MATCHER_TYPE_FIELD: &str = "inner_matcher_type";
 
-#[derive(Clone, Default, Debug)]
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
 pub struct PointPayload {
     pub encode: Vec<f32>,
     pub submodule_id: String,
@@ -16,6 +32,15 @@ pub struct PointPayload {
     pub uuid: String,
 }
 
+/// 指令匹配结果，`score`为相似度，越大越相似
+#[derive(Clone, Default, Debug)]
+pub struct MatchResult {
+    pub submodule_id: String,
+    pub instruct: String,
+    pub uuid: String,
+    pub score: f32,
+}
+
 impl PartialEq for PointPayload {
     fn eq(&self, other: &Self) -> bool {
         self.encode.eq(&other.encode)
@@ -28,9 +53,248 @@ pub trait InstructMatcher {
     where
         Self: Sized + Send + Sync;
 
-    async fn search(&self, point: Vec<f32>) -> Result<String>;
+    /// 返回相似度最高的`k`个指令点，按`score`由高到低排列，不做阈值过滤，
+    /// `instruct`为编码前的原始指令文本
+    async fn search_top_k(
+        &self,
+        instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>>;
+
+    /// 判断匹配结果是否达到匹配阈值
+    fn is_match(&self, match_result: &MatchResult) -> bool;
+
+    /// 匹配不使用指令编码时返回false，调用方可跳过编码，检索及插入的指令点编码为空
+    fn requires_encode(&self) -> bool {
+        true
+    }
+
+    /// `score`为距离时返回true，此时越小越相似
+    fn score_is_distance(&self) -> bool {
+        false
+    }
 
     async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()>;
 
     async fn remove_points(&mut self, points: Vec<PointPayload>) -> Result<()>;
+
+    /// 获取指定子模块已存储的指令点，用于重新注册时复用编码，不支持的实现返回空列表，
+    /// 复用的点不会再次插入，包装其他匹配器的实现可在此同步自身状态
+    async fn get_points(&mut self, _submodule_id: &str) -> Result<Vec<PointPayload>> {
+        Ok(Vec::new())
+    }
+
+    /// 移除不属于给定子模块的指令点，仅指令点保存在外部且可能残留的实现需要处理
+    async fn retain_submodules(&mut self, _submodule_ids: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// 根据配置创建指令匹配器
+pub async fn create_instruct_matcher(
+    instruct_matcher_config: &InstructMatcherConfig,
+) -> Result<Box<dyn InstructMatcher + Send + Sync>> {
+    Ok(match &instruct_matcher_config.instruct_matcher_type {
+        InstructMatcherType::GrpcQdrant => {
+            Box::new(GrpcQdrant::init(instruct_matcher_config).await?)
+        }
+        InstructMatcherType::InstantDistance => {
+            Box::new(InstantDistance::init(instruct_matcher_config).await?)
+        }
+        InstructMatcherType::ExactCosine => {
+            Box::new(ExactCosine::init(instruct_matcher_config).await?)
+        }
+        InstructMatcherType::Rule => Box::new(RuleMatcher::init(instruct_matcher_config).await?),
+        InstructMatcherType::Hybrid => {
+            Box::new(HybridMatcher::init(instruct_matcher_config).await?)
+        }
+    })
+}
+
+/// 创建被包装的内部匹配器，类型由`inner_matcher_type`配置，其余配置与外层共用
+pub async fn create_inner_instruct_matcher(
+    instruct_matcher_config: &InstructMatcherConfig,
+) -> Result<Box<dyn InstructMatcher + Send + Sync>> {
+    let inner_matcher_type = match instruct_matcher_config
+        .config_map
+        .get(INNER_MATCHER_TYPE_FIELD)
+    {
+        None => InstructMatcherType::default(),
+        Some(inner_matcher_type) => serde_json::from_value::<InstructMatcherType>(
+            serde_json::Value::String(inner_matcher_type.to_string()),
+        )?,
+    };
+    match inner_matcher_type {
+        InstructMatcherType::Rule | InstructMatcherType::Hybrid => Err(anyhow!(
+            "Config {:?} Value {:?} Cannot Wrap Another Matcher",
+            INNER_MATCHER_TYPE_FIELD,
+            inner_matcher_type
+        )),
+        inner_matcher_type => {
+            let mut inner_config = instruct_matcher_config.clone();
+            inner_config.instruct_matcher_type = inner_matcher_type;
+            create_instruct_matcher(&inner_config).await
+        }
+    }
+}
+
+/// 匹配阈值，按默认指令、子模块、全局阈值的优先级选取
+#[derive(Clone, Default, Debug)]
+pub struct ThresholdPolicy {
+    default_threshold: f32,
+    submodule_threshold: HashMap<String, f32>,
+    instruct_threshold: HashMap<String, HashMap<String, f32>>,
+}
+
+impl ThresholdPolicy {
+    pub fn new(instruct_matcher_config: &InstructMatcherConfig, default_threshold: f32) -> Self {
+        ThresholdPolicy {
+            default_threshold,
+            submodule_threshold: instruct_matcher_config.submodule_threshold.clone(),
+            instruct_threshold: instruct_matcher_config.instruct_threshold.clone(),
+        }
+    }
+
+    pub fn threshold(&self, match_result: &MatchResult) -> f32 {
+        if let Some(threshold) = self
+            .instruct_threshold
+            .get(&match_result.submodule_id)
+            .and_then(|instruct_threshold| instruct_threshold.get(&match_result.instruct))
+        {
+            return *threshold;
+        }
+        match self.submodule_threshold.get(&match_result.submodule_id) {
+            None => self.default_threshold,
+            Some(threshold) => *threshold,
+        }
+    }
+
+    /// `score`越大越相似时使用
+    pub fn is_match(&self, match_result: &MatchResult) -> bool {
+        match_result.score >= self.threshold(match_result)
+    }
+}
+
+/// 读取配置中的匹配阈值，未配置时使用默认值
+pub fn confidence_threshold(instruct_matcher_config: &InstructMatcherConfig) -> Result<f32> {
+    match instruct_matcher_config
+        .config_map
+        .get(CONFIDENCE_THRESHOLD_FIELD)
+    {
+        None => Ok(DEFAULT_CONFIDENCE_THRESHOLD),
+        Some(threshold) => Ok(threshold.parse::<f32>()?),
+    }
+}
+
+/// 按8路分组累加，便于编译器自动向量化
+pub fn dot(left: &[f32], right: &[f32]) -> f32 {
+    let mut lanes = [0.0f32; 8];
+    let left_chunks = left.chunks_exact(8);
+    let right_chunks = right.chunks_exact(8);
+    let left_remainder = left_chunks.remainder();
+    let right_remainder = right_chunks.remainder();
+    for (l, r) in left_chunks.zip(right_chunks) {
+        for i in 0..8 {
+            lanes[i] += l[i] * r[i];
+        }
+    }
+    let mut sum = lanes.iter().sum::<f32>();
+    for (l, r) in left_remainder.iter().zip(right_remainder.iter()) {
+        sum += l * r;
+    }
+    sum
+}
+
+/// 返回L2归一化后的向量，零向量原样返回
+pub fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+pub fn cosine_similarity(left: &[f32], right: &[f32]) -> f32 {
+    let mut dot = 0.0f32;
+    let mut left_norm = 0.0f32;
+    let mut right_norm = 0.0f32;
+    for (l, r) in left.iter().zip(right.iter()) {
+        dot += l * r;
+        left_norm += l * l;
+        right_norm += r * r;
+    }
+    if left_norm == 0.0 || right_norm == 0.0 {
+        return 0.0;
+    }
+    dot / (left_norm.sqrt() * right_norm.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_result(submodule_id: &str, instruct: &str, score: f32) -> MatchResult {
+        MatchResult {
+            submodule_id: submodule_id.to_string(),
+            instruct: instruct.to_string(),
+            score,
+            ..Default::default()
+        }
+    }
+
+    fn threshold_policy() -> ThresholdPolicy {
+        let mut instruct_matcher_config = InstructMatcherConfig::default();
+        instruct_matcher_config
+            .submodule_threshold
+            .insert("light".to_string(), 0.8);
+        instruct_matcher_config.instruct_threshold.insert(
+            "light".to_string(),
+            HashMap::from([("开灯".to_string(), 0.9)]),
+        );
+        ThresholdPolicy::new(&instruct_matcher_config, 0.7)
+    }
+
+    #[test]
+    fn threshold_prefers_instruct_then_submodule_then_default() {
+        let threshold_policy = threshold_policy();
+        assert_eq!(
+            threshold_policy.threshold(&match_result("light", "开灯", 0.0)),
+            0.9
+        );
+        assert_eq!(
+            threshold_policy.threshold(&match_result("light", "关灯", 0.0)),
+            0.8
+        );
+        assert_eq!(
+            threshold_policy.threshold(&match_result("music", "开灯", 0.0)),
+            0.7
+        );
+    }
+
+    #[test]
+    fn is_match_compares_score_with_threshold() {
+        let threshold_policy = threshold_policy();
+        assert!(threshold_policy.is_match(&match_result("light", "开灯", 0.9)));
+        assert!(!threshold_policy.is_match(&match_result("light", "开灯", 0.85)));
+        assert!(threshold_policy.is_match(&match_result("light", "关灯", 0.85)));
+        assert!(!threshold_policy.is_match(&match_result("music", "播放", 0.69)));
+    }
+
+    #[test]
+    fn confidence_threshold_uses_config_or_default() {
+        let mut instruct_matcher_config = InstructMatcherConfig::default();
+        assert_eq!(
+            confidence_threshold(&instruct_matcher_config).unwrap(),
+            DEFAULT_CONFIDENCE_THRESHOLD
+        );
+        instruct_matcher_config
+            .config_map
+            .insert(CONFIDENCE_THRESHOLD_FIELD.to_string(), "0.5".to_string());
+        assert_eq!(confidence_threshold(&instruct_matcher_config).unwrap(), 0.5);
+        instruct_matcher_config
+            .config_map
+            .insert(CONFIDENCE_THRESHOLD_FIELD.to_string(), "high".to_string());
+        assert!(confidence_threshold(&instruct_matcher_config).is_err());
+    }
 }
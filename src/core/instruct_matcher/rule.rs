@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use tracing::{debug, error, info};
+
+use crate::config::InstructMatcherConfig;
+use crate::core::instruct_matcher::{
+    create_inner_instruct_matcher, InstructMatcher, MatchResult, PointPayload,
+};
+
+pub const RULE_MODE_FIELD: &str = "rule_mode";
+pub const EXACT_PREFIX: &str = "exact:";
+pub const GLOB_PREFIX: &str = "glob:";
+pub const REGEX_PREFIX: &str = "regex:";
+const RULE_MATCH_SCORE: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleMode {
+    /// 只进行规则匹配，没有前缀的指令按精确匹配处理
+    Only,
+    /// 先进行规则匹配，未命中时交给内部向量匹配器，没有前缀的指令交给内部匹配器
+    Before,
+}
+
+enum RulePattern {
+    Exact(String),
+    Pattern(Regex),
+}
+
+impl RulePattern {
+    fn is_match(&self, instruct: &str) -> bool {
+        match self {
+            RulePattern::Exact(exact) => exact.eq(&normalize_instruct(instruct)),
+            RulePattern::Pattern(regex) => regex.is_match(instruct.trim()),
+        }
+    }
+}
+
+/// 精确匹配忽略首尾空白与大小写
+fn normalize_instruct(instruct: &str) -> String {
+    instruct.trim().to_lowercase()
+}
+
+/// 将glob转换为匹配整个指令的正则，支持`*`与`?`
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut pattern = String::from("(?i)^");
+    for c in glob.trim().chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            other => pattern.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    pattern.push('$');
+    Ok(Regex::new(&pattern)?)
+}
+
+/// 解析带前缀的默认指令，不是规则指令时返回`None`
+fn parse_rule(instruct: &str, rule_mode: RuleMode) -> Result<Option<RulePattern>> {
+    if let Some(exact) = instruct.strip_prefix(EXACT_PREFIX) {
+        Ok(Some(RulePattern::Exact(normalize_instruct(exact))))
+    } else if let Some(glob) = instruct.strip_prefix(GLOB_PREFIX) {
+        Ok(Some(RulePattern::Pattern(glob_to_regex(glob)?)))
+    } else if let Some(regex) = instruct.strip_prefix(REGEX_PREFIX) {
+        Ok(Some(RulePattern::Pattern(Regex::new(regex)?)))
+    } else if rule_mode == RuleMode::Only {
+        Ok(Some(RulePattern::Exact(normalize_instruct(instruct))))
+    } else {
+        Ok(None)
+    }
+}
+
+/// 基于规则的指令匹配器，子模块默认指令可通过`exact:`、`glob:`、`regex:`前缀声明为规则，
+/// 规则命中时`score`固定为1
+pub struct RuleMatcher {
+    rule_mode: RuleMode,
+    rules: Vec<(RulePattern, PointPayload)>,
+    inner_matcher: Option<Box<dyn InstructMatcher + Send + Sync>>,
+}
+
+#[async_trait]
+impl InstructMatcher for RuleMatcher {
+    async fn init(instruct_matcher_config: &InstructMatcherConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let rule_mode = match instruct_matcher_config
+            .config_map
+            .get(RULE_MODE_FIELD)
+            .map(|mode| mode.as_str())
+        {
+            None | Some("before") => RuleMode::Before,
+            Some("only") => RuleMode::Only,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"before\" Or \"only\"",
+                    RULE_MODE_FIELD,
+                    other
+                ))
+            }
+        };
+        let inner_matcher = match rule_mode {
+            RuleMode::Only => None,
+            RuleMode::Before => Some(create_inner_instruct_matcher(instruct_matcher_config).await?),
+        };
+        info!("Rule Matcher Mode: {:?}", rule_mode);
+        Ok(RuleMatcher {
+            rule_mode,
+            rules: Vec::new(),
+            inner_matcher,
+        })
+    }
+
+    /// 规则命中的指令点排在最前，不足`k`个时由内部匹配器补齐
+    async fn search_top_k(
+        &self,
+        instruct: &str,
+        point: Vec<f32>,
+        k: usize,
+    ) -> Result<Vec<MatchResult>> {
+        let mut match_results = self
+            .rules
+            .iter()
+            .filter(|(rule, _)| rule.is_match(instruct))
+            .take(k)
+            .map(|(_, point)| MatchResult {
+                submodule_id: point.submodule_id.to_string(),
+                instruct: point.instruct.to_string(),
+                uuid: point.uuid.to_string(),
+                score: RULE_MATCH_SCORE,
+            })
+            .collect::<Vec<MatchResult>>();
+        debug!("rule match result: {:?}", &match_results);
+        if match_results.len() < k {
+            if let Some(inner_matcher) = &self.inner_matcher {
+                match_results.append(
+                    &mut inner_matcher
+                        .search_top_k(instruct, point, k - match_results.len())
+                        .await?,
+                );
+            }
+        }
+        Ok(match_results)
+    }
+
+    fn is_match(&self, match_result: &MatchResult) -> bool {
+        if self
+            .rules
+            .iter()
+            .any(|(_, point)| point.uuid.eq(&match_result.uuid))
+        {
+            return true;
+        }
+        match &self.inner_matcher {
+            None => false,
+            Some(inner_matcher) => inner_matcher.is_match(match_result),
+        }
+    }
+
+    fn requires_encode(&self) -> bool {
+        self.inner_matcher.is_some()
+    }
+
+    /// 无法解析的规则跳过并输出日志，不影响同一批次的其他指令点
+    async fn append_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        let mut inner_points = Vec::<PointPayload>::new();
+        for point in points {
+            match parse_rule(&point.instruct, self.rule_mode) {
+                Ok(Some(rule)) => self.rules.push((rule, point)),
+                Ok(None) => inner_points.push(point),
+                Err(e) => error!(
+                    "Skip Submodule {:?} Instruct {:?}, Parse Rule Error: {}",
+                    &point.submodule_id, &point.instruct, e
+                ),
+            }
+        }
+        if let Some(inner_matcher) = &mut self.inner_matcher {
+            inner_matcher.append_points(inner_points).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_points(&mut self, points: Vec<PointPayload>) -> Result<()> {
+        let mut inner_points = Vec::<PointPayload>::new();
+        for point in points {
+            let rules_len = self.rules.len();
+            self.rules
+                .retain(|(_, rule_point)| !rule_point.uuid.eq(&point.uuid));
+            if self.rules.len() == rules_len {
+                inner_points.push(point);
+            }
+        }
+        if let Some(inner_matcher) = &mut self.inner_matcher {
+            inner_matcher.remove_points(inner_points).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_points(&mut self, submodule_id: &str) -> Result<Vec<PointPayload>> {
+        let mut points = self
+            .rules
+            .iter()
+            .map(|(_, point)| point)
+            .filter(|point| point.submodule_id.eq(submodule_id))
+            .cloned()
+            .collect::<Vec<PointPayload>>();
+        if let Some(inner_matcher) = &mut self.inner_matcher {
+            points.append(&mut inner_matcher.get_points(submodule_id).await?);
+        }
+        Ok(points)
+    }
+
+    async fn retain_submodules(&mut self, submodule_ids: &[String]) -> Result<()> {
+        self.rules
+            .retain(|(_, point)| submodule_ids.contains(&point.submodule_id));
+        if let Some(inner_matcher) = &mut self.inner_matcher {
+            inner_matcher.retain_submodules(submodule_ids).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_whole_instruct() {
+        let regex = glob_to_regex("打开*灯?").unwrap();
+        assert!(regex.is_match("打开客厅的灯吧"));
+        assert!(!regex.is_match("请打开客厅的灯吧"));
+        assert!(!regex.is_match("打开客厅的灯"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        let regex = glob_to_regex("1+1=?").unwrap();
+        assert!(regex.is_match("1+1=2"));
+        assert!(!regex.is_match("11=2"));
+        assert!(glob_to_regex("HELLO *").unwrap().is_match("hello world"));
+    }
+
+    #[test]
+    fn parse_rule_by_prefix() {
+        let exact = parse_rule("exact:  Hello ", RuleMode::Before)
+            .unwrap()
+            .unwrap();
+        assert!(exact.is_match("hello"));
+        assert!(!exact.is_match("hello world"));
+
+        let glob = parse_rule("glob:hello*", RuleMode::Before)
+            .unwrap()
+            .unwrap();
+        assert!(glob.is_match(" Hello World "));
+
+        let regex = parse_rule(r"regex:^\d+$", RuleMode::Before)
+            .unwrap()
+            .unwrap();
+        assert!(regex.is_match("123"));
+        assert!(!regex.is_match("12a"));
+
+        assert!(parse_rule("regex:(", RuleMode::Before).is_err());
+    }
+
+    #[test]
+    fn parse_rule_without_prefix_depends_on_mode() {
+        assert!(parse_rule("hello", RuleMode::Before).unwrap().is_none());
+        let exact = parse_rule("Hello", RuleMode::Only).unwrap().unwrap();
+        assert!(exact.is_match(" HELLO "));
+    }
+
+    #[tokio::test]
+    async fn invalid_rule_is_skipped_without_failing_batch() {
+        let mut config_map = std::collections::HashMap::<String, String>::new();
+        config_map.insert(RULE_MODE_FIELD.to_string(), "only".to_string());
+        let mut rule_matcher = RuleMatcher::init(&InstructMatcherConfig {
+            config_map,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(!rule_matcher.requires_encode());
+        let points = ["regex:(", "开灯", "glob:关*灯"]
+            .into_iter()
+            .map(|instruct| PointPayload {
+                submodule_id: "light".to_string(),
+                instruct: instruct.to_string(),
+                uuid: instruct.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<PointPayload>>();
+        rule_matcher.append_points(points).await.unwrap();
+        assert_eq!(rule_matcher.rules.len(), 2);
+        let match_results = rule_matcher
+            .search_top_k("关掉客厅灯", Vec::new(), 3)
+            .await
+            .unwrap();
+        assert_eq!(match_results[0].uuid, "glob:关*灯");
+    }
+}
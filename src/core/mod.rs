@@ -7,6 +7,7 @@ use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::Mutex;
 
+use crate::config::{InstructManagerConfig, ManipulateManagerConfig};
 use crate::core::core_thread::heartbeat_manager_thread;
 use crate::core::core_thread::instruct_manager::instruct_manager_thread;
 use crate::core::core_thread::manipulate_manager::manipulate_manager_thread;
@@ -15,6 +16,9 @@ use crate::core::instruct_encoder::InstructEncoder;
 use crate::core::instruct_matcher::InstructMatcher;
 use crate::core::operation_recorder::OperationRecorder;
 use crate::core::submodule_store::SubmoduleStore;
+use crate::entity::operation_record::{
+    InboundEntity, OperationOutcome, OperationRecord, RecordQuery,
+};
 
 pub mod core_thread;
 pub mod instruct_encoder;
@@ -31,17 +35,19 @@ type OperationRecorderImpl = Arc<Box<dyn OperationRecorder + Send + Sync>>;
 type HeartbeatManagerFn =
     dyn Fn(SubmoduleStoreImpl) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send;
 type InstructManagerFn = dyn Fn(
+        InstructManagerConfig,
         InstructEncoderImpl,
         InstructMatcherImpl,
         SubmoduleStoreImpl,
         OperationRecorderImpl,
-        UnboundedReceiver<InstructEntity>,
+        UnboundedReceiver<InboundEntity<InstructEntity>>,
     ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
     + Send;
 type ManipulateManagerFn = dyn Fn(
+        ManipulateManagerConfig,
         SubmoduleStoreImpl,
         OperationRecorderImpl,
-        UnboundedReceiver<ManipulateEntity>,
+        UnboundedReceiver<InboundEntity<ManipulateEntity>>,
     ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
     + Send;
 type SubmoduleManagerFn = dyn Fn(
@@ -49,7 +55,7 @@ type SubmoduleManagerFn = dyn Fn(
         InstructMatcherImpl,
         SubmoduleStoreImpl,
         OperationRecorderImpl,
-        UnboundedReceiver<ModuleOperate>,
+        UnboundedReceiver<InboundEntity<ModuleOperate>>,
     ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
     + Send;
 
@@ -66,12 +72,14 @@ pub struct NihilityCoreBuilder {
     instruct_matcher: Option<Box<dyn InstructMatcher + Send + Sync>>,
     submodule_store: Option<Box<dyn SubmoduleStore + Send + Sync>>,
     operation_recorder: Option<Box<dyn OperationRecorder + Send + Sync>>,
-    instruct_receiver: Option<UnboundedReceiver<InstructEntity>>,
-    manipulate_receiver: Option<UnboundedReceiver<ManipulateEntity>>,
-    module_operate_receiver: Option<UnboundedReceiver<ModuleOperate>>,
+    instruct_receiver: Option<UnboundedReceiver<InboundEntity<InstructEntity>>>,
+    manipulate_receiver: Option<UnboundedReceiver<InboundEntity<ManipulateEntity>>>,
+    module_operate_receiver: Option<UnboundedReceiver<InboundEntity<ModuleOperate>>>,
     heartbeat_manager_fn: Option<Box<HeartbeatManagerFn>>,
     instruct_manager_fn: Option<Box<InstructManagerFn>>,
+    instruct_manager_config: Option<InstructManagerConfig>,
     manipulate_manager_fn: Option<Box<ManipulateManagerFn>>,
+    manipulate_manager_config: Option<ManipulateManagerConfig>,
     submodule_manager_fn: Option<Box<SubmoduleManagerFn>>,
 }
 
@@ -112,6 +120,7 @@ impl NihilityCore {
                 heartbeat_manager_thread(heartbeat_manager_fn, core.submodule_store.clone())?;
                 instruct_manager_thread(
                     instruct_manager_fn,
+                    builder.instruct_manager_config.unwrap_or_default(),
                     core.instruct_encoder.clone(),
                     core.instruct_matcher.clone(),
                     core.submodule_store.clone(),
@@ -120,6 +129,7 @@ impl NihilityCore {
                 )?;
                 manipulate_manager_thread(
                     manipulate_manager_fn,
+                    builder.manipulate_manager_config.unwrap_or_default(),
                     core.submodule_store.clone(),
                     core.operation_recorder.clone(),
                     manipulate_receiver,
@@ -172,6 +182,22 @@ impl NihilityCore {
     }
 }
 
+impl NihilityCore {
+    pub async fn query_operation_record(query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        match CORE.get() {
+            None => Err(anyhow!("NihilityCore Not Build")),
+            Some(core) => core.operation_recorder.query(query).await,
+        }
+    }
+
+    pub async fn query_operation_outcome(record_id: &str) -> Result<Option<OperationOutcome>> {
+        match CORE.get() {
+            None => Err(anyhow!("NihilityCore Not Build")),
+            Some(core) => core.operation_recorder.query_outcome(record_id).await,
+        }
+    }
+}
+
 impl NihilityCoreBuilder {
     pub fn set_instruct_encoder(
         &mut self,
@@ -198,20 +224,23 @@ impl NihilityCoreBuilder {
         self.operation_recorder = Some(operation_recorder)
     }
 
-    pub fn set_instruct_receiver(&mut self, instruct_receiver: UnboundedReceiver<InstructEntity>) {
+    pub fn set_instruct_receiver(
+        &mut self,
+        instruct_receiver: UnboundedReceiver<InboundEntity<InstructEntity>>,
+    ) {
         self.instruct_receiver = Some(instruct_receiver)
     }
 
     pub fn set_manipulate_receiver(
         &mut self,
-        manipulate_receiver: UnboundedReceiver<ManipulateEntity>,
+        manipulate_receiver: UnboundedReceiver<InboundEntity<ManipulateEntity>>,
     ) {
         self.manipulate_receiver = Some(manipulate_receiver)
     }
 
     pub fn set_module_operate_receiver(
         &mut self,
-        module_operate_receiver: UnboundedReceiver<ModuleOperate>,
+        module_operate_receiver: UnboundedReceiver<InboundEntity<ModuleOperate>>,
     ) {
         self.module_operate_receiver = Some(module_operate_receiver)
     }
@@ -230,41 +259,54 @@ impl NihilityCoreBuilder {
     pub fn set_instruct_manager_fn<Fut>(
         &mut self,
         instruct_manager_fn: impl Fn(
+                InstructManagerConfig,
                 InstructEncoderImpl,
                 InstructMatcherImpl,
                 SubmoduleStoreImpl,
                 OperationRecorderImpl,
-                UnboundedReceiver<InstructEntity>,
+                UnboundedReceiver<InboundEntity<InstructEntity>>,
             ) -> Fut
             + 'static
             + Send,
     ) where
         Fut: Future<Output = Result<()>> + Send + 'static,
     {
-        self.instruct_manager_fn = Some(Box::new(move |a, b, c, d, e| {
-            Box::pin(instruct_manager_fn(a, b, c, d, e))
+        self.instruct_manager_fn = Some(Box::new(move |a, b, c, d, e, f| {
+            Box::pin(instruct_manager_fn(a, b, c, d, e, f))
                 as Pin<Box<dyn Future<Output = Result<()>> + Send>>
         }))
     }
 
+    pub fn set_instruct_manager_config(&mut self, instruct_manager_config: InstructManagerConfig) {
+        self.instruct_manager_config = Some(instruct_manager_config);
+    }
+
     pub fn set_manipulate_manager_fn<Fut>(
         &mut self,
         manipulate_manager_fn: impl Fn(
+                ManipulateManagerConfig,
                 SubmoduleStoreImpl,
                 OperationRecorderImpl,
-                UnboundedReceiver<ManipulateEntity>,
+                UnboundedReceiver<InboundEntity<ManipulateEntity>>,
             ) -> Fut
             + 'static
             + Send,
     ) where
         Fut: Future<Output = Result<()>> + Send + 'static,
     {
-        self.manipulate_manager_fn = Some(Box::new(move |a, b, c| {
-            Box::pin(manipulate_manager_fn(a, b, c))
+        self.manipulate_manager_fn = Some(Box::new(move |a, b, c, d| {
+            Box::pin(manipulate_manager_fn(a, b, c, d))
                 as Pin<Box<dyn Future<Output = Result<()>> + Send>>
         }))
     }
 
+    pub fn set_manipulate_manager_config(
+        &mut self,
+        manipulate_manager_config: ManipulateManagerConfig,
+    ) {
+        self.manipulate_manager_config = Some(manipulate_manager_config);
+    }
+
     pub fn set_submodule_manager_fn<Fut>(
         &mut self,
         submodule_manager_fn: impl Fn(
@@ -272,7 +314,7 @@ impl NihilityCoreBuilder {
                 InstructMatcherImpl,
                 SubmoduleStoreImpl,
                 OperationRecorderImpl,
-                UnboundedReceiver<ModuleOperate>,
+                UnboundedReceiver<InboundEntity<ModuleOperate>>,
             ) -> Fut
             + 'static
             + Send,
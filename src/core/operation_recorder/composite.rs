@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use tracing::{debug, info, warn};
+
+use crate::config::{OperationRecorderConfig, RecorderFailurePolicy};
+use crate::core::operation_recorder::{create_operation_recorder, OperationRecorder};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordQuery};
+
+/// 将每条记录分发给多个记录器，按各自的失败策略处理写入错误
+pub struct CompositeOperationRecorder {
+    recorders: Vec<(
+        RecorderFailurePolicy,
+        Box<dyn OperationRecorder + Send + Sync>,
+    )>,
+}
+
+impl CompositeOperationRecorder {
+    pub fn new(
+        recorders: Vec<(
+            RecorderFailurePolicy,
+            Box<dyn OperationRecorder + Send + Sync>,
+        )>,
+    ) -> Self {
+        CompositeOperationRecorder { recorders }
+    }
+
+    /// 所有记录器均已写入后再处理错误，避免一个记录器失败导致其余记录器丢失记录
+    fn apply_failure_policy(&self, results: Vec<Result<()>>) -> Result<()> {
+        let mut fail_error: Option<anyhow::Error> = None;
+        for ((failure_policy, _), result) in self.recorders.iter().zip(results) {
+            if let Err(e) = result {
+                match failure_policy {
+                    RecorderFailurePolicy::Ignore => {
+                        debug!("Ignore Operation Recorder Error: {}", e)
+                    }
+                    RecorderFailurePolicy::Warn => warn!("Operation Recorder Error: {}", e),
+                    RecorderFailurePolicy::Fail => {
+                        if fail_error.is_none() {
+                            fail_error = Some(e);
+                        }
+                    }
+                }
+            }
+        }
+        match fail_error {
+            None => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl OperationRecorder for CompositeOperationRecorder {
+    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        if operation_recorder_config.recorders.is_empty() {
+            return Err(anyhow!(
+                "Composite Operation Recorder Config recorders Is Empty"
+            ));
+        }
+        let mut recorders = Vec::new();
+        for recorder_config in operation_recorder_config.recorders.iter() {
+            info!(
+                "Composite Operation Recorder Add {:?} With Failure Policy {:?}",
+                &recorder_config.operation_recorder_type, &recorder_config.failure_policy
+            );
+            recorders.push((
+                recorder_config.failure_policy.clone(),
+                create_operation_recorder(recorder_config).await?,
+            ));
+        }
+        Ok(CompositeOperationRecorder::new(recorders))
+    }
+
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        let mut results = Vec::new();
+        for (_, recorder) in self.recorders.iter() {
+            results.push(recorder.recorder_instruct(record_id, instruct).await);
+        }
+        self.apply_failure_policy(results)
+    }
+
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        let mut results = Vec::new();
+        for (_, recorder) in self.recorders.iter() {
+            results.push(recorder.recorder_manipulate(record_id, manipulate).await);
+        }
+        self.apply_failure_policy(results)
+    }
+
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        let mut results = Vec::new();
+        for (_, recorder) in self.recorders.iter() {
+            results.push(
+                recorder
+                    .recorder_module_operate(record_id, module_operate)
+                    .await,
+            );
+        }
+        self.apply_failure_policy(results)
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        let mut results = Vec::new();
+        for (_, recorder) in self.recorders.iter() {
+            results.push(recorder.recorder_outcome(outcome).await);
+        }
+        self.apply_failure_policy(results)
+    }
+
+    /// 使用第一个支持查询的记录器
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        for (_, recorder) in self.recorders.iter() {
+            match recorder.query(query).await {
+                Ok(records) => return Ok(records),
+                Err(e) => debug!("Composite Operation Recorder Query Skip: {}", e),
+            }
+        }
+        Err(anyhow!("No Operation Recorder Support Query"))
+    }
+
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        for (_, recorder) in self.recorders.iter() {
+            match recorder.query_outcome(record_id).await {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => debug!("Composite Operation Recorder Query Skip: {}", e),
+            }
+        }
+        Err(anyhow!("No Operation Recorder Support Query"))
+    }
+
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        let mut results = Vec::new();
+        for (_, recorder) in self.recorders.iter() {
+            results.push(
+                recorder
+                    .apply_retention(expire_timestamp, max_records)
+                    .await,
+            );
+        }
+        self.apply_failure_policy(results)
+    }
+}
@@ -0,0 +1,564 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use tracing::{debug, info, warn};
+
+use crate::config::OperationRecorderConfig;
+use crate::core::operation_recorder::{current_timestamp, OperationRecorder};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordKind, RecordQuery};
+
+pub const FILE_PATH_FIELD: &str = "file_path";
+pub const ROTATE_TYPE_FIELD: &str = "rotate_type";
+pub const MAX_FILE_SIZE_FIELD: &str = "max_file_size";
+pub const MAX_FILES_FIELD: &str = "max_files";
+const DEFAULT_FILE_PATH: &str = "operation_record.jsonl";
+const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// 文件中的每一行，通过`type`字段区分记录与处理结果
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum JsonLine {
+    Record(OperationRecord),
+    Outcome(OperationOutcome),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RotateType {
+    Size,
+    Daily,
+}
+
+struct JsonLinesFile {
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+/// 记录文件的同步读写，均在阻塞线程池中调用
+struct JsonLinesStore {
+    file_path: String,
+    rotate_type: RotateType,
+    max_file_size: u64,
+    max_files: usize,
+    current_file: Mutex<JsonLinesFile>,
+}
+
+/// 每条记录写为一行json，按大小或按天轮转，保留`max_files`个历史文件
+pub struct JsonLinesOperationRecorder {
+    store: Arc<JsonLinesStore>,
+}
+
+fn rotated_file_path(file_path: &str, index: usize) -> String {
+    format!("{}.{}", file_path, index)
+}
+
+fn open_append(file_path: &str) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?)
+}
+
+/// 读取指定文件中的全部行，无法解析的行会被跳过
+pub fn read_json_lines(file_path: &str) -> Result<Vec<JsonLine>> {
+    let mut lines = Vec::<JsonLine>::new();
+    for (line_number, line) in BufReader::new(File::open(file_path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JsonLine>(&line) {
+            Ok(json_line) => lines.push(json_line),
+            Err(e) => warn!(
+                "Skip Invalid Line {} Of {:?}: {}",
+                line_number + 1,
+                file_path,
+                e
+            ),
+        }
+    }
+    Ok(lines)
+}
+
+fn record_kind_index(kind: RecordKind) -> usize {
+    match kind {
+        RecordKind::Instruct => 0,
+        RecordKind::Manipulate => 1,
+        RecordKind::ModuleOperate => 2,
+    }
+}
+
+/// 写入临时文件后替换原文件，并保留原文件的修改时间，不影响按时间的保留判断
+fn rewrite_json_lines(
+    file_path: &str,
+    json_lines: &[JsonLine],
+    modified: SystemTime,
+) -> Result<()> {
+    let temp_file_path = format!("{}.tmp", file_path);
+    let mut temp_file = File::create(&temp_file_path)?;
+    for json_line in json_lines {
+        writeln!(temp_file, "{}", serde_json::to_string(json_line)?)?;
+    }
+    temp_file.flush()?;
+    temp_file.set_modified(modified)?;
+    rename(&temp_file_path, file_path)?;
+    Ok(())
+}
+
+impl JsonLinesStore {
+    /// 当前文件与仍保留的历史文件，由新到旧排列
+    fn existing_file_paths(&self) -> Vec<String> {
+        let mut file_paths = vec![self.file_path.to_string()];
+        for index in 1..=self.max_files {
+            file_paths.push(rotated_file_path(&self.file_path, index));
+        }
+        file_paths
+            .into_iter()
+            .filter(|file_path| Path::new(file_path).exists())
+            .collect()
+    }
+
+    fn rotate(&self, current_file: &mut JsonLinesFile, day: u64) -> Result<()> {
+        current_file.file.flush()?;
+        let oldest_file_path = rotated_file_path(&self.file_path, self.max_files);
+        if Path::new(&oldest_file_path).exists() {
+            remove_file(&oldest_file_path)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let file_path = rotated_file_path(&self.file_path, index);
+            if Path::new(&file_path).exists() {
+                rename(&file_path, rotated_file_path(&self.file_path, index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            rename(&self.file_path, rotated_file_path(&self.file_path, 1))?;
+        } else {
+            remove_file(&self.file_path)?;
+        }
+        current_file.file = open_append(&self.file_path)?;
+        current_file.size = 0;
+        current_file.day = day;
+        debug!("Rotate Operation Record File {:?}", &self.file_path);
+        Ok(())
+    }
+
+    fn write_line(&self, json_line: &JsonLine) -> Result<()> {
+        let mut line = serde_json::to_string(json_line)?;
+        line.push('\n');
+        let day = current_timestamp()? / DAY_MILLIS;
+        let mut current_file = self
+            .current_file
+            .lock()
+            .map_err(|e| anyhow!("JsonLines Operation Recorder Lock Error: {}", e))?;
+        let need_rotate = match self.rotate_type {
+            RotateType::Size => {
+                current_file.size > 0 && current_file.size + line.len() as u64 > self.max_file_size
+            }
+            RotateType::Daily => current_file.size > 0 && current_file.day != day,
+        };
+        if need_rotate {
+            self.rotate(&mut current_file, day)?;
+        }
+        current_file.file.write_all(line.as_bytes())?;
+        current_file.file.flush()?;
+        current_file.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// 文件由新到旧读取，已收集到`offset + limit`条记录后不再读取更旧的文件
+    fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        let mut records = Vec::<OperationRecord>::new();
+        for file_path in self.existing_file_paths() {
+            if records.len() >= query.offset + query.limit {
+                break;
+            }
+            records.extend(
+                read_json_lines(&file_path)?
+                    .into_iter()
+                    .filter_map(|json_line| match json_line {
+                        JsonLine::Record(record) if query.matches(&record) => Some(record),
+                        _ => None,
+                    }),
+            );
+        }
+        records.sort_by_key(|record| Reverse(record.timestamp));
+        Ok(records
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .collect())
+    }
+
+    /// 找到处理结果后不再读取更旧的文件
+    fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        for file_path in self.existing_file_paths() {
+            let outcome = read_json_lines(&file_path)?
+                .into_iter()
+                .find_map(|json_line| match json_line {
+                    JsonLine::Outcome(outcome) if outcome.record_id.eq(record_id) => Some(outcome),
+                    _ => None,
+                });
+            if outcome.is_some() {
+                return Ok(outcome);
+            }
+        }
+        Ok(None)
+    }
+
+    /// 以文件为单位清理，当前写入的文件不会被删除
+    fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        // 持有写入锁，避免与轮转同时修改文件
+        let _current_file = self
+            .current_file
+            .lock()
+            .map_err(|e| anyhow!("JsonLines Operation Recorder Lock Error: {}", e))?;
+        let mut newer_counts = [0usize; 3];
+        for (index, file_path) in self.existing_file_paths().into_iter().enumerate() {
+            let modified = Path::new(&file_path).metadata()?.modified()?;
+            if let Some(expire_timestamp) = expire_timestamp {
+                let modified_timestamp = modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_millis() as u64);
+                if index > 0 && modified_timestamp < expire_timestamp {
+                    debug!("Retention Remove Operation Record File {:?}", &file_path);
+                    remove_file(&file_path)?;
+                    continue;
+                }
+            }
+            let max_records = match max_records {
+                None => continue,
+                Some(max_records) => max_records,
+            };
+            // 文件内由旧到新排列，倒序保留每类记录中较新的部分，当前文件始终保留
+            let json_lines = read_json_lines(&file_path)?;
+            let mut removed_record_ids = HashSet::<String>::new();
+            for json_line in json_lines.iter().rev() {
+                if let JsonLine::Record(record) = json_line {
+                    let newer_count = &mut newer_counts[record_kind_index(record.kind)];
+                    if index > 0 && *newer_count >= max_records {
+                        removed_record_ids.insert(record.id.to_string());
+                    } else {
+                        *newer_count += 1;
+                    }
+                }
+            }
+            if removed_record_ids.is_empty() {
+                continue;
+            }
+            let retained_lines = json_lines
+                .into_iter()
+                .filter(|json_line| match json_line {
+                    JsonLine::Record(record) => !removed_record_ids.contains(&record.id),
+                    JsonLine::Outcome(outcome) => !removed_record_ids.contains(&outcome.record_id),
+                })
+                .collect::<Vec<JsonLine>>();
+            debug!(
+                "Retention Remove {} Records From Operation Record File {:?}",
+                removed_record_ids.len(),
+                &file_path
+            );
+            if retained_lines.is_empty() {
+                remove_file(&file_path)?;
+            } else {
+                rewrite_json_lines(&file_path, &retained_lines, modified)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JsonLinesOperationRecorder {
+    async fn write_line(&self, json_line: JsonLine) -> Result<()> {
+        let store = self.store.clone();
+        // 文件写入为同步接口，放到阻塞线程池中执行，避免阻塞管理线程
+        spawn_blocking(move || store.write_line(&json_line)).await?
+    }
+}
+
+#[async_trait]
+impl OperationRecorder for JsonLinesOperationRecorder {
+    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let config_map = &operation_recorder_config.config_map;
+        let file_path = config_map
+            .get(FILE_PATH_FIELD)
+            .map_or(DEFAULT_FILE_PATH, |path| path.as_str())
+            .to_string();
+        let rotate_type = match config_map.get(ROTATE_TYPE_FIELD).map(|t| t.as_str()) {
+            None | Some("size") => RotateType::Size,
+            Some("daily") => RotateType::Daily,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Config {:?} Value {:?} Not Support, Expect \"size\" Or \"daily\"",
+                    ROTATE_TYPE_FIELD,
+                    other
+                ))
+            }
+        };
+        let max_file_size = match config_map.get(MAX_FILE_SIZE_FIELD) {
+            None => DEFAULT_MAX_FILE_SIZE,
+            Some(max_file_size) => max_file_size.parse::<u64>()?,
+        };
+        let max_files = match config_map.get(MAX_FILES_FIELD) {
+            None => DEFAULT_MAX_FILES,
+            Some(max_files) => max_files.parse::<usize>()?,
+        };
+        info!(
+            "JsonLines Operation Recorder Use File {:?}, Rotate Type {:?}, Max Files {}",
+            &file_path, rotate_type, max_files
+        );
+        let file = open_append(&file_path)?;
+        let metadata = file.metadata()?;
+        // 文件修改时间用于判断按天轮转时已有文件属于哪一天
+        let day = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_millis() as u64 / DAY_MILLIS,
+            Err(_) => current_timestamp()? / DAY_MILLIS,
+        };
+        Ok(JsonLinesOperationRecorder {
+            store: Arc::new(JsonLinesStore {
+                file_path,
+                rotate_type,
+                max_file_size,
+                max_files,
+                current_file: Mutex::new(JsonLinesFile {
+                    file,
+                    size: metadata.len(),
+                    day,
+                }),
+            }),
+        })
+    }
+
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        self.write_line(JsonLine::Record(OperationRecord::from_instruct(
+            record_id,
+            instruct,
+            current_timestamp()?,
+        )?))
+        .await
+    }
+
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        self.write_line(JsonLine::Record(OperationRecord::from_manipulate(
+            record_id,
+            manipulate,
+            current_timestamp()?,
+        )?))
+        .await
+    }
+
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        self.write_line(JsonLine::Record(OperationRecord::from_module_operate(
+            record_id,
+            module_operate,
+            current_timestamp()?,
+        )?))
+        .await
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        self.write_line(JsonLine::Outcome(outcome.clone())).await
+    }
+
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        let store = self.store.clone();
+        let query = query.clone();
+        spawn_blocking(move || store.query(&query)).await?
+    }
+
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        let store = self.store.clone();
+        let record_id = record_id.to_string();
+        spawn_blocking(move || store.query_outcome(&record_id)).await?
+    }
+
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        let store = self.store.clone();
+        spawn_blocking(move || store.apply_retention(expire_timestamp, max_records)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn test_config(dir: &Path, max_file_size: u64, max_files: usize) -> OperationRecorderConfig {
+        let mut config_map = HashMap::<String, String>::new();
+        config_map.insert(
+            FILE_PATH_FIELD.to_string(),
+            dir.join("record.jsonl").to_string_lossy().to_string(),
+        );
+        config_map.insert(MAX_FILE_SIZE_FIELD.to_string(), max_file_size.to_string());
+        config_map.insert(MAX_FILES_FIELD.to_string(), max_files.to_string());
+        OperationRecorderConfig {
+            config_map,
+            ..Default::default()
+        }
+    }
+
+    fn test_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("nihility_json_lines_{}", Uuid::new_v4()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn module_operate(name: &str) -> ModuleOperate {
+        ModuleOperate {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn module_operate_query(limit: usize) -> RecordQuery {
+        RecordQuery {
+            kind: RecordKind::ModuleOperate,
+            submodule_name: None,
+            start_time: None,
+            end_time: None,
+            offset: 0,
+            limit,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate_by_size_keeps_max_files() {
+        let dir = test_dir();
+        let recorder = JsonLinesOperationRecorder::init(&test_config(&dir, 1, 2))
+            .await
+            .unwrap();
+        for index in 0..5 {
+            recorder
+                .recorder_module_operate(&index.to_string(), &module_operate(&index.to_string()))
+                .await
+                .unwrap();
+        }
+        let file_path = dir.join("record.jsonl").to_string_lossy().to_string();
+        assert!(Path::new(&file_path).exists());
+        assert!(Path::new(&rotated_file_path(&file_path, 1)).exists());
+        assert!(Path::new(&rotated_file_path(&file_path, 2)).exists());
+        assert!(!Path::new(&rotated_file_path(&file_path, 3)).exists());
+
+        let mut names = recorder
+            .query(&module_operate_query(10))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.submodule_name)
+            .collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names, vec!["2", "3", "4"]);
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_stops_reading_after_limit() {
+        let dir = test_dir();
+        let recorder = JsonLinesOperationRecorder::init(&test_config(&dir, 1, 5))
+            .await
+            .unwrap();
+        for index in 0..3 {
+            recorder
+                .recorder_module_operate(&index.to_string(), &module_operate(&index.to_string()))
+                .await
+                .unwrap();
+        }
+        let records = recorder.query(&module_operate_query(1)).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].submodule_name, "2");
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_outcome_searches_rotated_files() {
+        let dir = test_dir();
+        let recorder = JsonLinesOperationRecorder::init(&test_config(&dir, 1, 5))
+            .await
+            .unwrap();
+        let mut outcome = OperationOutcome::new("record", RecordKind::Instruct);
+        outcome.error = Some("error".to_string());
+        recorder.recorder_outcome(&outcome).await.unwrap();
+        for index in 0..3 {
+            recorder
+                .recorder_module_operate(&index.to_string(), &module_operate(&index.to_string()))
+                .await
+                .unwrap();
+        }
+        let found = recorder.query_outcome("record").await.unwrap().unwrap();
+        assert_eq!(found.error.as_deref(), Some("error"));
+        assert!(recorder.query_outcome("missing").await.unwrap().is_none());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retention_keeps_each_kind_up_to_limit() {
+        let dir = test_dir();
+        let recorder = JsonLinesOperationRecorder::init(&test_config(&dir, 1, 10))
+            .await
+            .unwrap();
+        recorder
+            .recorder_manipulate("manipulate", &ManipulateEntity::default())
+            .await
+            .unwrap();
+        for index in 0..4 {
+            recorder
+                .recorder_module_operate(&index.to_string(), &module_operate(&index.to_string()))
+                .await
+                .unwrap();
+        }
+        // 子模块操作超出上限只删除较早的子模块操作，未达到上限的操作记录保留
+        recorder.apply_retention(None, Some(2)).await.unwrap();
+        let mut names = recorder
+            .query(&module_operate_query(10))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.submodule_name)
+            .collect::<Vec<String>>();
+        names.sort();
+        assert_eq!(names, vec!["2", "3"]);
+        let manipulates = recorder
+            .query(&RecordQuery {
+                kind: RecordKind::Manipulate,
+                ..module_operate_query(10)
+            })
+            .await
+            .unwrap();
+        assert_eq!(manipulates.len(), 1);
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,11 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
 use tracing::info;
 
 use crate::config::OperationRecorderConfig;
 use crate::core::operation_recorder::OperationRecorder;
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordQuery};
 
 #[derive(Default)]
 pub struct LogOperationRecorder;
@@ -18,18 +19,47 @@ impl OperationRecorder for LogOperationRecorder {
         Ok(LogOperationRecorder)
     }
 
-    async fn recorder_instruct(&self, instruct: &InstructEntity) -> Result<()> {
-        info!("Recorder Instruct: {:?}", instruct);
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        info!("Recorder Instruct {}: {:?}", record_id, instruct);
         Ok(())
     }
 
-    async fn recorder_manipulate(&self, manipulate: &ManipulateEntity) -> Result<()> {
-        info!("Recorder Manipulate: {:?}", manipulate);
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        info!("Recorder Manipulate {}: {:?}", record_id, manipulate);
         Ok(())
     }
 
-    async fn recorder_module_operate(&self, module_operate: &ModuleOperate) -> Result<()> {
-        info!("Recorder ModuleOperate: {:?}", module_operate);
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        info!("Recorder ModuleOperate {}: {:?}", record_id, module_operate);
+        Ok(())
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        info!("Recorder Outcome: {:?}", outcome);
+        Ok(())
+    }
+
+    async fn query(&self, _query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        Err(anyhow!("LogOperationRecorder Not Support Query"))
+    }
+
+    async fn query_outcome(&self, _record_id: &str) -> Result<Option<OperationOutcome>> {
+        Err(anyhow!("LogOperationRecorder Not Support Query"))
+    }
+
+    async fn apply_retention(
+        &self,
+        _expire_timestamp: Option<u64>,
+        _max_records: Option<usize>,
+    ) -> Result<()> {
         Ok(())
     }
 }
@@ -0,0 +1,159 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use tracing::info;
+
+use crate::config::OperationRecorderConfig;
+use crate::core::operation_recorder::{current_timestamp, OperationRecorder};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordKind, RecordQuery};
+
+pub const CAPACITY_FIELD: &str = "capacity";
+const DEFAULT_CAPACITY: usize = 10000;
+
+/// 仅保存在内存中的记录器，超出容量后丢弃最早的记录
+pub struct MemoryOperationRecorder {
+    capacity: usize,
+    records: Mutex<VecDeque<OperationRecord>>,
+    outcomes: Mutex<VecDeque<OperationOutcome>>,
+}
+
+fn push_bounded<T>(queue: &Mutex<VecDeque<T>>, capacity: usize, item: T) -> Result<()> {
+    let mut queue = queue
+        .lock()
+        .map_err(|e| anyhow!("Memory Operation Recorder Lock Error: {}", e))?;
+    if queue.len() >= capacity {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+    Ok(())
+}
+
+#[async_trait]
+impl OperationRecorder for MemoryOperationRecorder {
+    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let capacity = match operation_recorder_config.config_map.get(CAPACITY_FIELD) {
+            None => DEFAULT_CAPACITY,
+            Some(capacity) => capacity.parse::<usize>()?,
+        };
+        info!("Memory Operation Recorder Capacity: {}", capacity);
+        Ok(MemoryOperationRecorder {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            outcomes: Mutex::new(VecDeque::with_capacity(capacity)),
+        })
+    }
+
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        push_bounded(
+            &self.records,
+            self.capacity,
+            OperationRecord::from_instruct(record_id, instruct, current_timestamp()?)?,
+        )
+    }
+
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        push_bounded(
+            &self.records,
+            self.capacity,
+            OperationRecord::from_manipulate(record_id, manipulate, current_timestamp()?)?,
+        )
+    }
+
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        push_bounded(
+            &self.records,
+            self.capacity,
+            OperationRecord::from_module_operate(record_id, module_operate, current_timestamp()?)?,
+        )
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        push_bounded(&self.outcomes, self.capacity, outcome.clone())
+    }
+
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        let records = self
+            .records
+            .lock()
+            .map_err(|e| anyhow!("Memory Operation Recorder Lock Error: {}", e))?;
+        Ok(records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .skip(query.offset)
+            .take(query.limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        let outcomes = self
+            .outcomes
+            .lock()
+            .map_err(|e| anyhow!("Memory Operation Recorder Lock Error: {}", e))?;
+        Ok(outcomes
+            .iter()
+            .rev()
+            .find(|outcome| outcome.record_id.eq(record_id))
+            .cloned())
+    }
+
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        let mut records = self
+            .records
+            .lock()
+            .map_err(|e| anyhow!("Memory Operation Recorder Lock Error: {}", e))?;
+        if let Some(expire_timestamp) = expire_timestamp {
+            records.retain(|record| record.timestamp >= expire_timestamp);
+        }
+        if let Some(max_records) = max_records {
+            for kind in [
+                RecordKind::Instruct,
+                RecordKind::Manipulate,
+                RecordKind::ModuleOperate,
+            ] {
+                let mut remove_count = records
+                    .iter()
+                    .filter(|record| record.kind == kind)
+                    .count()
+                    .saturating_sub(max_records);
+                // 记录按时间先后存放，从头部开始删除最早的记录
+                records.retain(|record| {
+                    if remove_count > 0 && record.kind == kind {
+                        remove_count -= 1;
+                        return false;
+                    }
+                    true
+                });
+            }
+        }
+        let record_ids = records
+            .iter()
+            .map(|record| record.id.to_string())
+            .collect::<HashSet<String>>();
+        drop(records);
+        self.outcomes
+            .lock()
+            .map_err(|e| anyhow!("Memory Operation Recorder Lock Error: {}", e))?
+            .retain(|outcome| record_ids.contains(&outcome.record_id));
+        Ok(())
+    }
+}
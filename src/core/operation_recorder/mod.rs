@@ -1,13 +1,27 @@
-use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
 
+pub use composite::CompositeOperationRecorder;
+pub use json_lines::JsonLinesOperationRecorder;
 pub use log::LogOperationRecorder;
+pub use memory::MemoryOperationRecorder;
+pub use policy::PolicyOperationRecorder;
 pub use sqlite::SqliteOperationRecorder;
 
-use crate::config::OperationRecorderConfig;
+use crate::config::{OperationRecorderConfig, OperationRecorderType, RecorderFailurePolicy};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordQuery};
 
+mod composite;
+mod json_lines;
 mod log;
+mod memory;
+mod policy;
 mod sqlite;
 
 #[async_trait]
@@ -15,7 +29,112 @@ pub trait OperationRecorder {
     async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
     where
         Self: Sized + Send + Sync;
-    async fn recorder_instruct(&self, instruct: &InstructEntity) -> Result<()>;
-    async fn recorder_manipulate(&self, manipulate: &ManipulateEntity) -> Result<()>;
-    async fn recorder_module_operate(&self, module_operate: &ModuleOperate) -> Result<()>;
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()>;
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()>;
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()>;
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()>;
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>>;
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>>;
+    /// 删除时间戳早于`expire_timestamp`的记录，并使每类记录不超过`max_records`条
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()>;
+}
+
+/// 根据配置创建记录器，配置了保留或脱敏规则时包装为策略记录器，
+/// 非`Fail`失败策略的记录器再包装为组合记录器以应用该策略
+pub async fn create_operation_recorder(
+    operation_recorder_config: &OperationRecorderConfig,
+) -> Result<Box<dyn OperationRecorder + Send + Sync>> {
+    let recorder: Box<dyn OperationRecorder + Send + Sync> =
+        match &operation_recorder_config.operation_recorder_type {
+            OperationRecorderType::Log => {
+                Box::new(LogOperationRecorder::init(operation_recorder_config).await?)
+            }
+            OperationRecorderType::Sqlite => {
+                Box::new(SqliteOperationRecorder::init(operation_recorder_config).await?)
+            }
+            OperationRecorderType::Memory => {
+                Box::new(MemoryOperationRecorder::init(operation_recorder_config).await?)
+            }
+            OperationRecorderType::JsonLines => {
+                Box::new(JsonLinesOperationRecorder::init(operation_recorder_config).await?)
+            }
+            OperationRecorderType::Composite => {
+                Box::new(CompositeOperationRecorder::init(operation_recorder_config).await?)
+            }
+        };
+    let recorder: Box<dyn OperationRecorder + Send + Sync> =
+        if policy::has_policy(operation_recorder_config) {
+            Box::new(PolicyOperationRecorder::new(
+                operation_recorder_config,
+                recorder,
+            )?)
+        } else {
+            recorder
+        };
+    match &operation_recorder_config.failure_policy {
+        RecorderFailurePolicy::Fail => Ok(recorder),
+        other_policy => Ok(Box::new(CompositeOperationRecorder::new(vec![(
+            other_policy.clone(),
+            recorder,
+        )]))),
+    }
+}
+
+/// Sqlite数据库文件头
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// 文件名为`*.jsonl`或轮转后的`*.jsonl.<序号>`时视为JsonLines文件
+fn is_json_lines_file(file_path: &str) -> bool {
+    let file_name = Path::new(file_path)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match file_name.rsplit_once('.') {
+        Some((base_name, index)) if index.chars().all(|c| c.is_ascii_digit()) => {
+            base_name.ends_with(".jsonl")
+        }
+        _ => file_name.ends_with(".jsonl"),
+    }
+}
+
+/// 从记录器写出的文件中读取记录，按文件头识别Sqlite数据库，按文件名识别JsonLines文件，结果按时间正序排列
+pub fn load_operation_records(file_path: &str) -> Result<Vec<OperationRecord>> {
+    let mut header = [0u8; 16];
+    let is_sqlite =
+        File::open(file_path)?.read_exact(&mut header).is_ok() && header == SQLITE_HEADER;
+    if is_sqlite {
+        sqlite::read_sqlite_records(file_path)
+    } else if is_json_lines_file(file_path) {
+        let mut records = json_lines::read_json_lines(file_path)?
+            .into_iter()
+            .filter_map(|json_line| match json_line {
+                json_lines::JsonLine::Record(record) => Some(record),
+                json_lines::JsonLine::Outcome(_) => None,
+            })
+            .collect::<Vec<OperationRecord>>();
+        records.sort_by_key(|record| record.timestamp);
+        Ok(records)
+    } else {
+        Err(anyhow!(
+            "Record File {:?} Is Neither A Sqlite Database Nor A JsonLines File",
+            file_path
+        ))
+    }
+}
+
+/// 记录使用毫秒时间戳，便于按原始间隔回放
+fn current_timestamp() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
 }
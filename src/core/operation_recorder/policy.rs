@@ -0,0 +1,213 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use nihility_common::{
+    InstructData, InstructEntity, ManipulateData, ManipulateEntity, ModuleOperate,
+};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tokio::spawn;
+use tracing::{debug, error, info};
+
+use crate::config::{OperationRecorderConfig, RedactionRule};
+use crate::core::operation_recorder::{
+    create_operation_recorder, current_timestamp, OperationRecorder,
+};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordQuery};
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+enum Redaction {
+    Mask(Regex, String),
+    Hash,
+}
+
+/// 在写入实际记录器之前对指令与操作文本脱敏，并定期在后台清理超出保留策略的记录
+pub struct PolicyOperationRecorder {
+    inner: Arc<Box<dyn OperationRecorder + Send + Sync>>,
+    retention_days: u64,
+    retention_max_records: usize,
+    redactions: Vec<Redaction>,
+    last_retention: Mutex<Option<Instant>>,
+}
+
+pub fn has_policy(operation_recorder_config: &OperationRecorderConfig) -> bool {
+    operation_recorder_config.retention_days > 0
+        || operation_recorder_config.retention_max_records > 0
+        || !operation_recorder_config.redaction_rules.is_empty()
+}
+
+impl PolicyOperationRecorder {
+    pub fn new(
+        operation_recorder_config: &OperationRecorderConfig,
+        inner: Box<dyn OperationRecorder + Send + Sync>,
+    ) -> Result<Self> {
+        let mut redactions = Vec::<Redaction>::new();
+        for rule in operation_recorder_config.redaction_rules.iter() {
+            redactions.push(match rule {
+                RedactionRule::Mask {
+                    pattern,
+                    replacement,
+                } => Redaction::Mask(Regex::new(pattern)?, replacement.to_string()),
+                RedactionRule::Hash => Redaction::Hash,
+            });
+        }
+        info!(
+            "Operation Recorder Retention Days: {}, Max Records: {}, Redaction Rules: {}",
+            operation_recorder_config.retention_days,
+            operation_recorder_config.retention_max_records,
+            redactions.len()
+        );
+        Ok(PolicyOperationRecorder {
+            inner: Arc::new(inner),
+            retention_days: operation_recorder_config.retention_days,
+            retention_max_records: operation_recorder_config.retention_max_records,
+            redactions,
+            last_retention: Mutex::new(None),
+        })
+    }
+
+    fn redact(&self, text: &str) -> String {
+        let mut result = text.to_string();
+        for redaction in self.redactions.iter() {
+            result = match redaction {
+                Redaction::Mask(regex, replacement) => {
+                    regex.replace_all(&result, replacement.as_str()).to_string()
+                }
+                Redaction::Hash => format!("sha256:{}", hex::encode(Sha256::digest(&result))),
+            };
+        }
+        result
+    }
+
+    /// 距上次清理超过间隔时在后台执行一次保留策略，清理失败只记录日志，不影响记录写入
+    fn retention_if_due(&self) -> Result<()> {
+        if self.retention_days == 0 && self.retention_max_records == 0 {
+            return Ok(());
+        }
+        {
+            let mut last_retention = self
+                .last_retention
+                .lock()
+                .map_err(|e| anyhow!("Policy Operation Recorder Lock Error: {}", e))?;
+            if let Some(last_retention) = *last_retention {
+                if last_retention.elapsed() < RETENTION_INTERVAL {
+                    return Ok(());
+                }
+            }
+            *last_retention = Some(Instant::now());
+        }
+        let expire_timestamp = match self.retention_days {
+            0 => None,
+            retention_days => {
+                Some(current_timestamp()?.saturating_sub(retention_days * DAY_MILLIS))
+            }
+        };
+        let max_records = match self.retention_max_records {
+            0 => None,
+            max_records => Some(max_records),
+        };
+        debug!(
+            "Apply Operation Recorder Retention, Expire Timestamp: {:?}, Max Records: {:?}",
+            expire_timestamp, max_records
+        );
+        let inner = self.inner.clone();
+        spawn(async move {
+            if let Err(e) = inner.apply_retention(expire_timestamp, max_records).await {
+                error!("Apply Operation Recorder Retention Error: {}", e);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OperationRecorder for PolicyOperationRecorder {
+    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        // 去除策略配置后创建实际记录器，避免重复包装
+        let mut inner_config = operation_recorder_config.clone();
+        inner_config.retention_days = 0;
+        inner_config.retention_max_records = 0;
+        inner_config.redaction_rules.clear();
+        let inner = create_operation_recorder(&inner_config).await?;
+        PolicyOperationRecorder::new(operation_recorder_config, inner)
+    }
+
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        if self.redactions.is_empty() {
+            self.inner.recorder_instruct(record_id, instruct).await?;
+        } else {
+            let mut redacted_instruct = instruct.clone();
+            match &instruct.instruct {
+                InstructData::Text(text) => {
+                    redacted_instruct.instruct = InstructData::Text(self.redact(text))
+                }
+            }
+            self.inner
+                .recorder_instruct(record_id, &redacted_instruct)
+                .await?;
+        }
+        self.retention_if_due()
+    }
+
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        match &manipulate.manipulate {
+            ManipulateData::Text(text) if !self.redactions.is_empty() => {
+                let mut redacted_manipulate = manipulate.clone();
+                redacted_manipulate.manipulate = ManipulateData::Text(self.redact(text));
+                self.inner
+                    .recorder_manipulate(record_id, &redacted_manipulate)
+                    .await?;
+            }
+            _ => {
+                self.inner
+                    .recorder_manipulate(record_id, manipulate)
+                    .await?;
+            }
+        }
+        self.retention_if_due()
+    }
+
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        self.inner
+            .recorder_module_operate(record_id, module_operate)
+            .await?;
+        self.retention_if_due()
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        self.inner.recorder_outcome(outcome).await
+    }
+
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        self.inner.query(query).await
+    }
+
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        self.inner.query_outcome(record_id).await
+    }
+
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        self.inner
+            .apply_retention(expire_timestamp, max_records)
+            .await
+    }
+}
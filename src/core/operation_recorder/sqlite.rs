@@ -1,27 +1,356 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use crate::core::operation_recorder::OperationRecorder;
-use anyhow::Result;
 use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, Value, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension};
+use tokio::task::spawn_blocking;
+use tracing::{debug, info};
+
 use crate::config::OperationRecorderConfig;
+use crate::core::operation_recorder::{current_timestamp, OperationRecorder};
+use crate::entity::operation_record::{OperationOutcome, OperationRecord, RecordKind, RecordQuery};
+
+pub const DATABASE_PATH_FIELD: &str = "database_path";
+pub const JOURNAL_MODE_FIELD: &str = "journal_mode";
+const DEFAULT_DATABASE_PATH: &str = "operation_record.db";
+const DEFAULT_JOURNAL_MODE: &str = "WAL";
+const INSTRUCT_TABLE: &str = "instruct_record";
+const MANIPULATE_TABLE: &str = "manipulate_record";
+const MODULE_OPERATE_TABLE: &str = "module_operate_record";
+const OUTCOME_TABLE: &str = "operation_outcome";
+
+pub struct SqliteOperationRecorder {
+    connection: Arc<Mutex<Connection>>,
+}
+
+fn table_name(kind: RecordKind) -> &'static str {
+    match kind {
+        RecordKind::Instruct => INSTRUCT_TABLE,
+        RecordKind::Manipulate => MANIPULATE_TABLE,
+        RecordKind::ModuleOperate => MODULE_OPERATE_TABLE,
+    }
+}
+
+/// 以只读方式读取数据库中全部记录，按时间正序排列
+pub fn read_sqlite_records(database_path: &str) -> Result<Vec<OperationRecord>> {
+    let connection = Connection::open_with_flags(database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut records = Vec::<OperationRecord>::new();
+    for kind in [
+        RecordKind::Instruct,
+        RecordKind::Manipulate,
+        RecordKind::ModuleOperate,
+    ] {
+        let mut statement = connection.prepare(&format!(
+            "SELECT id, timestamp, submodule_name, operate_type, payload FROM {} ORDER BY timestamp",
+            table_name(kind)
+        ))?;
+        let mut kind_records = statement
+            .query_map([], |row| {
+                Ok(OperationRecord {
+                    id: row.get(0)?,
+                    kind,
+                    timestamp: row.get(1)?,
+                    submodule_name: row.get(2)?,
+                    operate_type: row.get(3)?,
+                    payload: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<OperationRecord>>>()?;
+        records.append(&mut kind_records);
+    }
+    records.sort_by_key(|record| record.timestamp);
+    Ok(records)
+}
+
+/// 处理结果表中的`kind`列按名称存储
+impl FromSql for RecordKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse::<RecordKind>()
+            .map_err(|e| FromSqlError::Other(e.into()))
+    }
+}
 
-#[derive(Default)]
-pub struct SqliteOperationRecorder;
+impl SqliteOperationRecorder {
+    async fn insert_record(&self, record: OperationRecord) -> Result<()> {
+        let connection = self.connection.clone();
+        // rusqlite为同步接口，放到阻塞线程池中执行，避免阻塞管理线程
+        spawn_blocking(move || -> Result<()> {
+            let connection = connection
+                .lock()
+                .map_err(|e| anyhow!("Sqlite Connection Lock Error: {}", e))?;
+            connection.execute(
+                &format!(
+                    "INSERT INTO {} (id, timestamp, submodule_name, operate_type, payload) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    table_name(record.kind)
+                ),
+                params![
+                    record.id,
+                    record.timestamp,
+                    record.submodule_name,
+                    record.operate_type,
+                    record.payload
+                ],
+            )?;
+            debug!("Insert {:?} Record {:?}", record.kind, record.id);
+            Ok(())
+        })
+        .await?
+    }
+}
 
 #[async_trait]
 impl OperationRecorder for SqliteOperationRecorder {
-    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self> where Self: Sized + Send + Sync {
-        todo!()
+    async fn init(operation_recorder_config: &OperationRecorderConfig) -> Result<Self>
+    where
+        Self: Sized + Send + Sync,
+    {
+        let database_path = operation_recorder_config
+            .config_map
+            .get(DATABASE_PATH_FIELD)
+            .map_or(DEFAULT_DATABASE_PATH, |path| path.as_str())
+            .to_string();
+        let journal_mode = operation_recorder_config
+            .config_map
+            .get(JOURNAL_MODE_FIELD)
+            .map_or(DEFAULT_JOURNAL_MODE, |mode| mode.as_str())
+            .to_string();
+        info!(
+            "Sqlite Operation Recorder Use Database {:?}, Journal Mode {:?}",
+            &database_path, &journal_mode
+        );
+        let connection = spawn_blocking(move || -> Result<Connection> {
+            let connection = Connection::open(&database_path)?;
+            let current_journal_mode: String =
+                connection.pragma_update_and_check(None, "journal_mode", &journal_mode, |row| {
+                    row.get(0)
+                })?;
+            debug!("Sqlite Journal Mode: {:?}", current_journal_mode);
+            for table in [INSTRUCT_TABLE, MANIPULATE_TABLE, MODULE_OPERATE_TABLE] {
+                connection.execute_batch(&format!(
+                    "CREATE TABLE IF NOT EXISTS {table} (
+                        id TEXT PRIMARY KEY NOT NULL,
+                        timestamp INTEGER NOT NULL,
+                        submodule_name TEXT NOT NULL,
+                        operate_type TEXT NOT NULL,
+                        payload TEXT NOT NULL
+                    );
+                    CREATE INDEX IF NOT EXISTS {table}_timestamp_index ON {table} (timestamp);
+                    CREATE INDEX IF NOT EXISTS {table}_submodule_name_index ON {table} (submodule_name, timestamp);"
+                ))?;
+            }
+            connection.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {OUTCOME_TABLE} (
+                    record_id TEXT PRIMARY KEY NOT NULL,
+                    timestamp INTEGER NOT NULL,
+                    kind TEXT NOT NULL,
+                    matched_submodule TEXT,
+                    matched_instruct TEXT,
+                    score REAL,
+                    forward_latency INTEGER,
+                    response_code TEXT,
+                    error TEXT
+                );
+                CREATE INDEX IF NOT EXISTS {OUTCOME_TABLE}_matched_submodule_index ON {OUTCOME_TABLE} (matched_submodule, timestamp);"
+            ))?;
+            Ok(connection)
+        })
+        .await??;
+        Ok(SqliteOperationRecorder {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn recorder_instruct(&self, record_id: &str, instruct: &InstructEntity) -> Result<()> {
+        self.insert_record(OperationRecord::from_instruct(
+            record_id,
+            instruct,
+            current_timestamp()?,
+        )?)
+        .await
+    }
+
+    async fn recorder_manipulate(
+        &self,
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+    ) -> Result<()> {
+        self.insert_record(OperationRecord::from_manipulate(
+            record_id,
+            manipulate,
+            current_timestamp()?,
+        )?)
+        .await
     }
 
-    async fn recorder_instruct(&self, instruct: &InstructEntity) -> Result<()> {
-        todo!()
+    async fn recorder_module_operate(
+        &self,
+        record_id: &str,
+        module_operate: &ModuleOperate,
+    ) -> Result<()> {
+        self.insert_record(OperationRecord::from_module_operate(
+            record_id,
+            module_operate,
+            current_timestamp()?,
+        )?)
+        .await
+    }
+
+    async fn recorder_outcome(&self, outcome: &OperationOutcome) -> Result<()> {
+        let connection = self.connection.clone();
+        let outcome = outcome.clone();
+        let timestamp = current_timestamp()?;
+        spawn_blocking(move || -> Result<()> {
+            let connection = connection
+                .lock()
+                .map_err(|e| anyhow!("Sqlite Connection Lock Error: {}", e))?;
+            connection.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (record_id, timestamp, kind, matched_submodule, matched_instruct, score, forward_latency, response_code, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    OUTCOME_TABLE
+                ),
+                params![
+                    outcome.record_id,
+                    timestamp,
+                    outcome.kind.as_str(),
+                    outcome.matched_submodule,
+                    outcome.matched_instruct,
+                    outcome.score,
+                    outcome.forward_latency,
+                    outcome.response_code,
+                    outcome.error
+                ],
+            )?;
+            debug!("Insert Outcome Of Record {:?}", outcome.record_id);
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn query(&self, query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        let connection = self.connection.clone();
+        let query = query.clone();
+        spawn_blocking(move || -> Result<Vec<OperationRecord>> {
+            let mut conditions = Vec::<&str>::new();
+            let mut values = Vec::<Value>::new();
+            if let Some(submodule_name) = query.submodule_name {
+                conditions.push("submodule_name = ?");
+                values.push(Value::Text(submodule_name));
+            }
+            if let Some(start_time) = query.start_time {
+                conditions.push("timestamp >= ?");
+                values.push(Value::Integer(start_time as i64));
+            }
+            if let Some(end_time) = query.end_time {
+                conditions.push("timestamp <= ?");
+                values.push(Value::Integer(end_time as i64));
+            }
+            let where_clause = if conditions.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            };
+            values.push(Value::Integer(query.limit as i64));
+            values.push(Value::Integer(query.offset as i64));
+
+            let connection = connection
+                .lock()
+                .map_err(|e| anyhow!("Sqlite Connection Lock Error: {}", e))?;
+            let mut statement = connection.prepare(&format!(
+                "SELECT id, timestamp, submodule_name, operate_type, payload FROM {} {} ORDER BY timestamp DESC LIMIT ? OFFSET ?",
+                table_name(query.kind),
+                where_clause
+            ))?;
+            let records = statement
+                .query_map(params_from_iter(values), |row| {
+                    Ok(OperationRecord {
+                        id: row.get(0)?,
+                        kind: query.kind,
+                        timestamp: row.get(1)?,
+                        submodule_name: row.get(2)?,
+                        operate_type: row.get(3)?,
+                        payload: row.get(4)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<OperationRecord>>>()?;
+            Ok(records)
+        })
+        .await?
     }
 
-    async fn recorder_manipulate(&self, manipulate: &ManipulateEntity) -> Result<()> {
-        todo!()
+    async fn query_outcome(&self, record_id: &str) -> Result<Option<OperationOutcome>> {
+        let connection = self.connection.clone();
+        let record_id = record_id.to_string();
+        spawn_blocking(move || -> Result<Option<OperationOutcome>> {
+            let connection = connection
+                .lock()
+                .map_err(|e| anyhow!("Sqlite Connection Lock Error: {}", e))?;
+            let outcome = connection
+                .query_row(
+                    &format!(
+                        "SELECT record_id, kind, matched_submodule, matched_instruct, score, forward_latency, response_code, error FROM {} WHERE record_id = ?1",
+                        OUTCOME_TABLE
+                    ),
+                    params![record_id],
+                    |row| {
+                        Ok(OperationOutcome {
+                            record_id: row.get(0)?,
+                            kind: row.get(1)?,
+                            matched_submodule: row.get(2)?,
+                            matched_instruct: row.get(3)?,
+                            score: row.get(4)?,
+                            forward_latency: row.get(5)?,
+                            response_code: row.get(6)?,
+                            error: row.get(7)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(outcome)
+        })
+        .await?
     }
 
-    async fn recorder_module_operate(&self, module_operate: &ModuleOperate) -> Result<()> {
-        todo!()
+    async fn apply_retention(
+        &self,
+        expire_timestamp: Option<u64>,
+        max_records: Option<usize>,
+    ) -> Result<()> {
+        let connection = self.connection.clone();
+        spawn_blocking(move || -> Result<()> {
+            let connection = connection
+                .lock()
+                .map_err(|e| anyhow!("Sqlite Connection Lock Error: {}", e))?;
+            let mut removed = 0usize;
+            for table in [INSTRUCT_TABLE, MANIPULATE_TABLE, MODULE_OPERATE_TABLE] {
+                if let Some(expire_timestamp) = expire_timestamp {
+                    removed += connection.execute(
+                        &format!("DELETE FROM {} WHERE timestamp < ?1", table),
+                        params![expire_timestamp],
+                    )?;
+                }
+                if let Some(max_records) = max_records {
+                    removed += connection.execute(
+                        &format!(
+                            "DELETE FROM {table} WHERE id NOT IN (SELECT id FROM {table} ORDER BY timestamp DESC LIMIT ?1)"
+                        ),
+                        params![max_records as i64],
+                    )?;
+                }
+            }
+            // 原始记录删除后，对应的处理结果一并删除
+            connection.execute(
+                &format!(
+                    "DELETE FROM {OUTCOME_TABLE} WHERE record_id NOT IN (SELECT id FROM {INSTRUCT_TABLE} UNION SELECT id FROM {MANIPULATE_TABLE})"
+                ),
+                [],
+            )?;
+            debug!("Sqlite Retention Removed {} Records", removed);
+            Ok(())
+        })
+        .await?
     }
-}
\ No newline at end of file
+}
@@ -1,31 +1,81 @@
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::anyhow;
 use anyhow::Result;
 use async_trait::async_trait;
+use tracing::{info, warn};
 
 use crate::config::SubmoduleStoreConfig;
-use crate::core::submodule_store::SubmoduleStore;
+use crate::core::submodule_store::{
+    SubmoduleHealth, SubmoduleStore, OFFLINE_THRESHOLD_FIELD, OPEN_DURATION_MS_FIELD,
+    UNHEALTHY_THRESHOLD_FIELD,
+};
 use crate::entity::submodule::Submodule;
 
+const DEFAULT_UNHEALTHY_THRESHOLD: u32 = 3;
+const DEFAULT_OFFLINE_THRESHOLD: u32 = 10;
+const DEFAULT_OPEN_DURATION_MS: u64 = 30000;
+
 #[derive(Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// 熔断结束后已放行试探转发，结果返回前不再放行
+    probing: bool,
+}
+
 pub struct HashMapSubmoduleStore {
     inner_data: HashMap<String, Submodule>,
+    circuit_states: HashMap<String, CircuitState>,
+    unhealthy_threshold: u32,
+    offline_threshold: u32,
+    open_duration: Duration,
 }
 
 #[async_trait]
 impl SubmoduleStore for HashMapSubmoduleStore {
-    async fn init(_submodule_store_config: &SubmoduleStoreConfig) -> Result<Self>
+    async fn init(submodule_store_config: &SubmoduleStoreConfig) -> Result<Self>
     where
         Self: Sized + Send + Sync,
     {
+        let config_map = &submodule_store_config.config_map;
+        let unhealthy_threshold = match config_map.get(UNHEALTHY_THRESHOLD_FIELD) {
+            None => DEFAULT_UNHEALTHY_THRESHOLD,
+            Some(unhealthy_threshold) => unhealthy_threshold.parse::<u32>()?.max(1),
+        };
+        let offline_threshold = match config_map.get(OFFLINE_THRESHOLD_FIELD) {
+            None => DEFAULT_OFFLINE_THRESHOLD,
+            Some(offline_threshold) => offline_threshold.parse::<u32>()?.max(1),
+        };
+        if offline_threshold < unhealthy_threshold {
+            return Err(anyhow!(
+                "Config {:?} Value {} Must Not Be Less Than {:?} Value {}",
+                OFFLINE_THRESHOLD_FIELD,
+                offline_threshold,
+                UNHEALTHY_THRESHOLD_FIELD,
+                unhealthy_threshold
+            ));
+        }
+        let open_duration = match config_map.get(OPEN_DURATION_MS_FIELD) {
+            None => Duration::from_millis(DEFAULT_OPEN_DURATION_MS),
+            Some(open_duration) => Duration::from_millis(open_duration.parse::<u64>()?),
+        };
+        info!(
+            "HashMapSubmoduleStore Unhealthy Threshold: {}, Offline Threshold: {}, Open Duration: {:?}",
+            unhealthy_threshold, offline_threshold, open_duration
+        );
         Ok(HashMapSubmoduleStore {
             inner_data: HashMap::new(),
+            circuit_states: HashMap::new(),
+            unhealthy_threshold,
+            offline_threshold,
+            open_duration,
         })
     }
 
     async fn insert(&mut self, submodule: Submodule) -> Result<()> {
+        self.circuit_states.remove(&submodule.name);
         self.inner_data
             .insert(submodule.name.to_string(), submodule);
         Ok(())
@@ -62,9 +112,217 @@ impl SubmoduleStore for HashMapSubmoduleStore {
     }
 
     async fn remove_submodule(&mut self, name: &String) -> Result<Submodule> {
+        self.circuit_states.remove(name);
         match self.inner_data.remove(name) {
             None => Err(anyhow!("Cannot Find Named {} Submodule", name)),
             Some(submodule) => Ok(submodule),
         }
     }
+
+    async fn get_submodule_names(&self) -> Result<Vec<String>> {
+        Ok(self.inner_data.keys().cloned().collect())
+    }
+
+    async fn is_available(&mut self, name: &str) -> Result<bool> {
+        Ok(match self.circuit_states.get_mut(name) {
+            Some(CircuitState {
+                open_until: Some(open_until),
+                probing,
+                ..
+            }) => {
+                if *probing || Instant::now() < *open_until {
+                    false
+                } else {
+                    *probing = true;
+                    true
+                }
+            }
+            _ => true,
+        })
+    }
+
+    /// 熔断结束后的转发再次失败时重新熔断，成功时恢复健康，
+    /// 仅在连续失败次数首次达到下线阈值时返回`Offline`
+    async fn record_forward_result(
+        &mut self,
+        name: &str,
+        success: bool,
+    ) -> Result<SubmoduleHealth> {
+        // 转发期间子模块可能已经下线
+        if !self.inner_data.contains_key(name) {
+            return Ok(SubmoduleHealth::Healthy);
+        }
+        let circuit_state = self.circuit_states.entry(name.to_string()).or_default();
+        if success {
+            if circuit_state.consecutive_failures >= self.unhealthy_threshold {
+                info!("Submodule {:?} Recover From Unhealthy", name);
+            }
+            *circuit_state = CircuitState::default();
+            return Ok(SubmoduleHealth::Healthy);
+        }
+        circuit_state.consecutive_failures += 1;
+        circuit_state.probing = false;
+        if circuit_state.consecutive_failures < self.unhealthy_threshold {
+            return Ok(SubmoduleHealth::Healthy);
+        }
+        circuit_state.open_until = Some(Instant::now() + self.open_duration);
+        if circuit_state.consecutive_failures == self.offline_threshold {
+            return Ok(SubmoduleHealth::Offline);
+        }
+        warn!(
+            "Submodule {:?} Unhealthy, Forward Failed {} Times In A Row",
+            name, circuit_state.consecutive_failures
+        );
+        Ok(SubmoduleHealth::Unhealthy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nihility_common::{ClientType, ConnectionType, GrpcClient, GrpcClientConfig};
+
+    use super::*;
+
+    const NAME: &str = "submodule";
+
+    async fn submodule_store(
+        unhealthy_threshold: u32,
+        offline_threshold: u32,
+        open_duration_ms: u64,
+    ) -> Result<HashMapSubmoduleStore> {
+        let mut config_map = HashMap::<String, String>::new();
+        config_map.insert(
+            UNHEALTHY_THRESHOLD_FIELD.to_string(),
+            unhealthy_threshold.to_string(),
+        );
+        config_map.insert(
+            OFFLINE_THRESHOLD_FIELD.to_string(),
+            offline_threshold.to_string(),
+        );
+        config_map.insert(
+            OPEN_DURATION_MS_FIELD.to_string(),
+            open_duration_ms.to_string(),
+        );
+        let mut submodule_store = HashMapSubmoduleStore::init(&SubmoduleStoreConfig {
+            config_map,
+            ..Default::default()
+        })
+        .await?;
+        submodule_store
+            .insert(Submodule {
+                name: NAME.to_string(),
+                auth_id: String::new(),
+                default_instruct_map: HashMap::new(),
+                connection_type: ConnectionType::GrpcType,
+                client_type: ClientType::NotReceiveType,
+                heartbeat_time: 0,
+                client: Arc::new(Box::new(GrpcClient::init(GrpcClientConfig::default()))),
+            })
+            .await?;
+        Ok(submodule_store)
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_then_goes_offline() {
+        let mut submodule_store = submodule_store(2, 3, 60000).await.unwrap();
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Healthy
+        );
+        assert!(submodule_store.is_available(NAME).await.unwrap());
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Unhealthy
+        );
+        assert!(!submodule_store.is_available(NAME).await.unwrap());
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Offline
+        );
+        assert!(!submodule_store.is_available(NAME).await.unwrap());
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Unhealthy
+        );
+    }
+
+    #[tokio::test]
+    async fn success_after_open_duration_recovers() {
+        let mut submodule_store = submodule_store(1, 5, 0).await.unwrap();
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Unhealthy
+        );
+        assert!(submodule_store.is_available(NAME).await.unwrap());
+        assert!(!submodule_store.is_available(NAME).await.unwrap());
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, true)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Healthy
+        );
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Unhealthy
+        );
+    }
+
+    #[tokio::test]
+    async fn equal_thresholds_go_offline_directly() {
+        let mut submodule_store = submodule_store(2, 2, 60000).await.unwrap();
+        submodule_store
+            .record_forward_result(NAME, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Offline
+        );
+    }
+
+    #[tokio::test]
+    async fn offline_threshold_below_unhealthy_is_rejected() {
+        assert!(submodule_store(3, 2, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn removed_submodule_is_not_tracked() {
+        let mut submodule_store = submodule_store(1, 1, 60000).await.unwrap();
+        submodule_store
+            .remove_submodule(&NAME.to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            submodule_store
+                .record_forward_result(NAME, false)
+                .await
+                .unwrap(),
+            SubmoduleHealth::Healthy
+        );
+        assert!(submodule_store.is_available(NAME).await.unwrap());
+    }
 }
@@ -8,6 +8,22 @@ mod hash_map;
 use crate::config::SubmoduleStoreConfig;
 pub use hash_map::HashMapSubmoduleStore;
 
+/// 子模块连续转发失败达到该次数后熔断，熔断期间不再转发
+pub const UNHEALTHY_THRESHOLD_FIELD: &str = "unhealthy_threshold";
+/// 子模块连续转发失败达到该次数后下线，不能小于`unhealthy_threshold`
+pub const OFFLINE_THRESHOLD_FIELD: &str = "offline_threshold";
+/// 熔断持续时间，结束后允许再次尝试转发
+pub const OPEN_DURATION_MS_FIELD: &str = "open_duration_ms";
+
+/// 记录转发结果后子模块的健康状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubmoduleHealth {
+    Healthy,
+    Unhealthy,
+    /// 连续失败次数达到下线阈值，需要下线该子模块
+    Offline,
+}
+
 #[async_trait]
 pub trait SubmoduleStore {
     async fn init(submodule_store_config: &SubmoduleStoreConfig) -> Result<Self>
@@ -19,4 +35,9 @@ pub trait SubmoduleStore {
     async fn update_heartbeat(&mut self, name: &String) -> Result<()>;
     async fn get_expire_heartbeat_submodule(&self, expire_time: u64) -> Result<Vec<String>>;
     async fn remove_submodule(&mut self, name: &String) -> Result<Submodule>;
+    async fn get_submodule_names(&self) -> Result<Vec<String>>;
+    /// 子模块是否可以转发，熔断期间返回false，熔断结束后只放行一次试探转发
+    async fn is_available(&mut self, name: &str) -> Result<bool>;
+    async fn record_forward_result(&mut self, name: &str, success: bool)
+        -> Result<SubmoduleHealth>;
 }
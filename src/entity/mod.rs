@@ -1 +1,2 @@
+pub mod operation_record;
 pub mod submodule;
@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use nihility_common::InstructData::Text;
+use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Instruct,
+    Manipulate,
+    ModuleOperate,
+}
+
+/// 各类记录统一的存储结构，原始实体序列化为json存放于`payload`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OperationRecord {
+    pub id: String,
+    pub kind: RecordKind,
+    pub timestamp: u64,
+    pub submodule_name: String,
+    pub operate_type: String,
+    pub payload: String,
+}
+
+/// 指令或操作的处理结果，通过`record_id`关联原始记录
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OperationOutcome {
+    pub record_id: String,
+    pub kind: RecordKind,
+    pub matched_submodule: Option<String>,
+    pub matched_instruct: Option<String>,
+    pub score: Option<f32>,
+    pub forward_latency: Option<u64>,
+    pub response_code: Option<String>,
+    pub error: Option<String>,
+}
+
+/// 送入核心处理线程的实体，`replayed`为true时由回放发送，处理过程中不再记录
+#[derive(Debug, Clone)]
+pub struct InboundEntity<T> {
+    pub entity: T,
+    pub replayed: bool,
+}
+
+/// 按类型查询记录，结果按时间倒序排列
+#[derive(Debug, Clone)]
+pub struct RecordQuery {
+    pub kind: RecordKind,
+    pub submodule_name: Option<String>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl RecordKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordKind::Instruct => "Instruct",
+            RecordKind::Manipulate => "Manipulate",
+            RecordKind::ModuleOperate => "ModuleOperate",
+        }
+    }
+}
+
+impl FromStr for RecordKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "Instruct" => Ok(RecordKind::Instruct),
+            "Manipulate" => Ok(RecordKind::Manipulate),
+            "ModuleOperate" => Ok(RecordKind::ModuleOperate),
+            other => Err(anyhow!("Unknown Record Kind {:?}", other)),
+        }
+    }
+}
+
+impl OperationRecord {
+    pub fn from_instruct(
+        record_id: &str,
+        instruct: &InstructEntity,
+        timestamp: u64,
+    ) -> Result<Self> {
+        let operate_type = match &instruct.instruct {
+            Text(_) => "Text".to_string(),
+        };
+        Ok(OperationRecord {
+            id: record_id.to_string(),
+            kind: RecordKind::Instruct,
+            timestamp,
+            submodule_name: instruct.info.receive_manipulate_submodule.to_string(),
+            operate_type,
+            payload: serde_json::to_string(instruct)?,
+        })
+    }
+
+    pub fn from_manipulate(
+        record_id: &str,
+        manipulate: &ManipulateEntity,
+        timestamp: u64,
+    ) -> Result<Self> {
+        Ok(OperationRecord {
+            id: record_id.to_string(),
+            kind: RecordKind::Manipulate,
+            timestamp,
+            submodule_name: manipulate.info.use_module_name.to_string(),
+            operate_type: format!("{:?}", manipulate.info.manipulate_type),
+            payload: serde_json::to_string(manipulate)?,
+        })
+    }
+
+    pub fn from_module_operate(
+        record_id: &str,
+        module_operate: &ModuleOperate,
+        timestamp: u64,
+    ) -> Result<Self> {
+        Ok(OperationRecord {
+            id: record_id.to_string(),
+            kind: RecordKind::ModuleOperate,
+            timestamp,
+            submodule_name: module_operate.name.to_string(),
+            operate_type: format!("{:?}", module_operate.operate_type),
+            payload: serde_json::to_string(module_operate)?,
+        })
+    }
+}
+
+impl<T> InboundEntity<T> {
+    pub fn live(entity: T) -> Self {
+        InboundEntity {
+            entity,
+            replayed: false,
+        }
+    }
+
+    pub fn replayed(entity: T) -> Self {
+        InboundEntity {
+            entity,
+            replayed: true,
+        }
+    }
+}
+
+impl OperationOutcome {
+    pub fn new(record_id: &str, kind: RecordKind) -> Self {
+        OperationOutcome {
+            record_id: record_id.to_string(),
+            kind,
+            matched_submodule: None,
+            matched_instruct: None,
+            score: None,
+            forward_latency: None,
+            response_code: None,
+            error: None,
+        }
+    }
+}
+
+impl RecordQuery {
+    pub fn new(kind: RecordKind) -> Self {
+        RecordQuery {
+            kind,
+            submodule_name: None,
+            start_time: None,
+            end_time: None,
+            offset: 0,
+            limit: DEFAULT_QUERY_LIMIT,
+        }
+    }
+
+    pub fn matches(&self, record: &OperationRecord) -> bool {
+        record.kind == self.kind
+            && self
+                .submodule_name
+                .as_ref()
+                .is_none_or(|name| record.submodule_name.eq(name))
+            && self
+                .start_time
+                .is_none_or(|start_time| record.timestamp >= start_time)
+            && self
+                .end_time
+                .is_none_or(|end_time| record.timestamp <= end_time)
+    }
+}
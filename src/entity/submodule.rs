@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
@@ -17,7 +18,8 @@ pub struct Submodule {
     pub connection_type: ConnectionType,
     pub client_type: ClientType,
     pub heartbeat_time: u64,
-    pub client: Box<dyn NihilityClient + Send + Sync>,
+    /// 转发时先克隆再释放存储锁，避免远程调用期间阻塞其他线程
+    pub client: Arc<Box<dyn NihilityClient + Send + Sync>>,
 }
 
 impl Submodule {
@@ -70,7 +72,7 @@ impl Submodule {
                             connection_type: ConnectionType::GrpcType,
                             client_type,
                             heartbeat_time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
-                            client: Box::new(client),
+                            client: Arc::new(Box::new(client)),
                         })
                     }
                     ConnectionType::PipeType => {
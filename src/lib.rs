@@ -2,7 +2,7 @@ extern crate nihility_common;
 
 use std::sync::OnceLock;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use lazy_static::lazy_static;
 use nihility_common::{
     core_authentication_core_init, InstructEntity, ManipulateEntity, ModuleOperate,
@@ -14,38 +14,43 @@ use tokio_util::sync::CancellationToken;
 use crate::check::check;
 pub use crate::config::NihilityTerminalConfig;
 use crate::config::{
-    HeartbeatManagerType, InstructEncoderType, InstructManagerType, InstructMatcherType,
-    ManipulateManagerType, OperationRecorderType, SubmoduleManagerType, SubmoduleStoreType,
+    HeartbeatManagerType, InstructManagerType, ManipulateManagerType, SubmoduleManagerType,
+    SubmoduleStoreType,
 };
 use crate::core::core_thread::heartbeat_manager::simple_heartbeat_manager_thread;
-use crate::core::core_thread::instruct_manager::simple_instruct_manager_thread;
+use crate::core::core_thread::instruct_manager::{
+    concurrent_instruct_manager_thread, simple_instruct_manager_thread,
+};
 use crate::core::core_thread::manipulate_manager::simple_manipulate_manager_thread;
 use crate::core::core_thread::submodule_manager::simple_submodule_manager_thread;
-use crate::core::instruct_encoder::sentence_transformers::SentenceTransformers;
-use crate::core::instruct_encoder::InstructEncoder;
-use crate::core::instruct_matcher::grpc_qdrant::GrpcQdrant;
-use crate::core::instruct_matcher::instant_distance::InstantDistance;
-use crate::core::instruct_matcher::InstructMatcher;
-pub use crate::core::instruct_matcher::ENCODE_SIZE_FIELD;
-use crate::core::operation_recorder::{
-    LogOperationRecorder, OperationRecorder, SqliteOperationRecorder,
-};
+use crate::core::instruct_encoder::create_instruct_encoder;
+use crate::core::instruct_matcher::create_instruct_matcher;
+pub use crate::core::instruct_matcher::{ENCODE_SIZE_FIELD, MODEL_IDENTITY_FIELD};
+use crate::core::operation_recorder::create_operation_recorder;
 use crate::core::submodule_store::{HashMapSubmoduleStore, SubmoduleStore};
 use crate::core::{NihilityCore, NihilityCoreBuilder};
+use crate::entity::operation_record::InboundEntity;
+pub use crate::entity::operation_record::{
+    OperationOutcome, OperationRecord, RecordKind, RecordQuery,
+};
 
 pub mod check;
 mod config;
 mod core;
 mod entity;
+mod replay;
 mod server;
 
 lazy_static! {
     static ref CANCELLATION_TOKEN: CancellationToken = CancellationToken::new();
 }
 static CLOSE_SENDER: OnceLock<WeakSender<String>> = OnceLock::new();
-static MODULE_OPERATE_SENDER: OnceLock<WeakUnboundedSender<ModuleOperate>> = OnceLock::new();
-static INSTRUCT_SENDER: OnceLock<WeakUnboundedSender<InstructEntity>> = OnceLock::new();
-static MANIPULATE_SENDER: OnceLock<WeakUnboundedSender<ManipulateEntity>> = OnceLock::new();
+static MODULE_OPERATE_SENDER: OnceLock<WeakUnboundedSender<InboundEntity<ModuleOperate>>> =
+    OnceLock::new();
+static INSTRUCT_SENDER: OnceLock<WeakUnboundedSender<InboundEntity<InstructEntity>>> =
+    OnceLock::new();
+static MANIPULATE_SENDER: OnceLock<WeakUnboundedSender<InboundEntity<ManipulateEntity>>> =
+    OnceLock::new();
 
 pub struct NihilityTerminal;
 
@@ -58,12 +63,27 @@ impl NihilityTerminal {
         CANCELLATION_TOKEN.clone()
     }
 
+    pub async fn query_operation_record(query: &RecordQuery) -> Result<Vec<OperationRecord>> {
+        NihilityCore::query_operation_record(query).await
+    }
+
+    pub async fn query_operation_outcome(record_id: &str) -> Result<Option<OperationOutcome>> {
+        NihilityCore::query_operation_outcome(record_id).await
+    }
+
+    /// 需在`start`之后调用，将记录文件中的操作重新送入核心处理
+    pub async fn replay(file_path: &str, keep_timing: bool) -> Result<()> {
+        replay::replay(file_path, keep_timing).await
+    }
+
     pub async fn start(summary_config: NihilityTerminalConfig) -> Result<()> {
         core_authentication_core_init(&summary_config.core.auth_key_dir)?;
 
-        let (module_operate_se, module_operate_re) = mpsc::unbounded_channel::<ModuleOperate>();
-        let (instruct_se, instruct_re) = mpsc::unbounded_channel::<InstructEntity>();
-        let (manipulate_se, manipulate_re) = mpsc::unbounded_channel::<ManipulateEntity>();
+        let (module_operate_se, module_operate_re) =
+            mpsc::unbounded_channel::<InboundEntity<ModuleOperate>>();
+        let (instruct_se, instruct_re) = mpsc::unbounded_channel::<InboundEntity<InstructEntity>>();
+        let (manipulate_se, manipulate_re) =
+            mpsc::unbounded_channel::<InboundEntity<ManipulateEntity>>();
 
         MODULE_OPERATE_SENDER.get_or_init(|| module_operate_se.downgrade());
         INSTRUCT_SENDER.get_or_init(|| instruct_se.downgrade());
@@ -77,24 +97,32 @@ impl NihilityTerminal {
         core_builder.set_manipulate_receiver(manipulate_re);
         core_builder.set_module_operate_receiver(module_operate_re);
 
-        core_builder.set_instruct_encoder(
-            match &summary_config.core.instruct_encoder.instruct_encoder_type {
-                InstructEncoderType::SentenceTransformers => Box::new(
-                    SentenceTransformers::init(&summary_config.core.instruct_encoder).await?,
-                ),
-            },
+        let instruct_encoder =
+            create_instruct_encoder(&summary_config.core.instruct_encoder).await?;
+        // 匹配器的编码维度以编码器实际输出为准，显式配置的维度不一致时直接报错
+        let encode_size = instruct_encoder.encode_size().await;
+        let mut instruct_matcher_config = summary_config.core.instruct_matcher.clone();
+        if let Some(config_encode_size) = instruct_matcher_config.config_map.get(ENCODE_SIZE_FIELD)
+        {
+            if config_encode_size.parse::<u64>()? != encode_size {
+                return Err(anyhow!(
+                    "Instruct Matcher Config {:?} Value {} Not Match Instruct Encoder Encode Size {}",
+                    ENCODE_SIZE_FIELD,
+                    config_encode_size,
+                    encode_size
+                ));
+            }
+        }
+        instruct_matcher_config
+            .config_map
+            .insert(ENCODE_SIZE_FIELD.to_string(), encode_size.to_string());
+        instruct_matcher_config.config_map.insert(
+            MODEL_IDENTITY_FIELD.to_string(),
+            instruct_encoder.model_identity(),
         );
+        core_builder.set_instruct_encoder(instruct_encoder);
 
-        core_builder.set_instruct_matcher(
-            match &summary_config.core.instruct_matcher.instruct_matcher_type {
-                InstructMatcherType::GrpcQdrant => {
-                    Box::new(GrpcQdrant::init(&summary_config.core.instruct_matcher).await?)
-                }
-                InstructMatcherType::InstantDistance => {
-                    Box::new(InstantDistance::init(&summary_config.core.instruct_matcher).await?)
-                }
-            },
-        );
+        core_builder.set_instruct_matcher(create_instruct_matcher(&instruct_matcher_config).await?);
 
         core_builder.set_submodule_store(
             match &summary_config.core.submodule_store.submodule_store_type {
@@ -105,31 +133,34 @@ impl NihilityTerminal {
         );
 
         core_builder.set_operation_recorder(
-            match &summary_config
-                .core
-                .operation_recorder
-                .operation_recorder_type
-            {
-                OperationRecorderType::Log => Box::new(
-                    LogOperationRecorder::init(&summary_config.core.operation_recorder).await?,
-                ),
-                OperationRecorderType::Sqlite => Box::new(
-                    SqliteOperationRecorder::init(&summary_config.core.operation_recorder).await?,
-                ),
-            },
+            create_operation_recorder(&summary_config.core.operation_recorder).await?,
         );
 
         core_builder.set_heartbeat_manager_fn(match &summary_config.core.heartbeat_manager {
             HeartbeatManagerType::Simple => simple_heartbeat_manager_thread,
         });
 
-        core_builder.set_instruct_manager_fn(match &summary_config.core.instruct_manager {
-            InstructManagerType::Simple => simple_instruct_manager_thread,
-        });
-
-        core_builder.set_manipulate_manager_fn(match &summary_config.core.manipulate_manager {
-            ManipulateManagerType::Simple => simple_manipulate_manager_thread,
-        });
+        core_builder.set_instruct_manager_config(summary_config.core.instruct_manager.clone());
+        // 不同管理线程函数返回的Future类型不同，需要分别设置
+        match &summary_config.core.instruct_manager.instruct_manager_type {
+            InstructManagerType::Simple => {
+                core_builder.set_instruct_manager_fn(simple_instruct_manager_thread)
+            }
+            InstructManagerType::Concurrent => {
+                core_builder.set_instruct_manager_fn(concurrent_instruct_manager_thread)
+            }
+        }
+
+        core_builder.set_manipulate_manager_config(summary_config.core.manipulate_manager.clone());
+        core_builder.set_manipulate_manager_fn(
+            match &summary_config
+                .core
+                .manipulate_manager
+                .manipulate_manager_type
+            {
+                ManipulateManagerType::Simple => simple_manipulate_manager_thread,
+            },
+        );
 
         core_builder.set_submodule_manager_fn(match &summary_config.core.submodule_manager {
             SubmoduleManagerType::Simple => simple_submodule_manager_thread,
@@ -1,7 +1,7 @@
 use nihility_common::Log;
 use tokio::sync::mpsc;
 use tokio::{select, signal};
-use tracing::info;
+use tracing::{error, info};
 
 use nihility_terminal::{NihilityTerminal, NihilityTerminalConfig};
 use nihility_terminal::check::check;
@@ -21,6 +21,21 @@ pub async fn main() {
      /:/  /      \/__/         /:/  /      \/__/        \:\__\   \/__/                                    \:\__\        |:|  |
      \/__/                     \/__/                     \/__/                                             \/__/         \|__|    "#
     );
+    // 子命令：nihility-terminal replay <file> [--keep-timing]
+    let args: Vec<String> = std::env::args().collect();
+    let replay_args = match args.get(1).map(|arg| arg.as_str()) {
+        Some("replay") => match args.get(2) {
+            Some(file_path) => Some((
+                file_path.to_string(),
+                args.iter().skip(3).any(|arg| arg.eq("--keep-timing")),
+            )),
+            None => {
+                println!("Usage: nihility-terminal replay <file> [--keep-timing]");
+                return;
+            }
+        },
+        _ => None,
+    };
     check().expect("Check Lib Or Model File Fail");
     let (shutdown_se, mut shutdown_re) = mpsc::channel::<String>(4);
     let cancellation_token = NihilityTerminal::get_cancellation_token();
@@ -29,6 +44,12 @@ pub async fn main() {
     Log::init(&summary_config.log).expect("Log Init Error");
     if let Err(e) = NihilityTerminal::start(summary_config).await {
         println!("{:?}", e);
+    } else if let Some((file_path, keep_timing)) = replay_args {
+        tokio::spawn(async move {
+            if let Err(e) = NihilityTerminal::replay(&file_path, keep_timing).await {
+                error!("Replay {:?} Error: {}", &file_path, e);
+            }
+        });
     }
     drop(shutdown_se);
     select! {
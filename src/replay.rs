@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use nihility_common::{InstructEntity, ManipulateEntity, ModuleOperate};
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+use crate::core::operation_recorder::load_operation_records;
+use crate::entity::operation_record::{InboundEntity, RecordKind};
+use crate::{CANCELLATION_TOKEN, INSTRUCT_SENDER, MANIPULATE_SENDER, MODULE_OPERATE_SENDER};
+
+/// 将记录文件中的操作按原始顺序重新发送到与`server_start`相同的通道中，
+/// `keep_timing`为true时按记录间的原始间隔发送，回放的操作不会再次被记录
+pub async fn replay(file_path: &str, keep_timing: bool) -> Result<()> {
+    let records = load_operation_records(file_path)?;
+    info!(
+        "Replay {} Records From {:?}, Keep Timing: {}",
+        records.len(),
+        file_path,
+        keep_timing
+    );
+    let instruct_sender = INSTRUCT_SENDER
+        .get()
+        .and_then(|sender| sender.upgrade())
+        .ok_or(anyhow!("Instruct Sender Not Init"))?;
+    let manipulate_sender = MANIPULATE_SENDER
+        .get()
+        .and_then(|sender| sender.upgrade())
+        .ok_or(anyhow!("Manipulate Sender Not Init"))?;
+    let module_operate_sender = MODULE_OPERATE_SENDER
+        .get()
+        .and_then(|sender| sender.upgrade())
+        .ok_or(anyhow!("Module Operate Sender Not Init"))?;
+
+    let mut last_timestamp: Option<u64> = None;
+    for record in records {
+        if CANCELLATION_TOKEN.is_cancelled() {
+            info!("Replay Cancelled");
+            return Ok(());
+        }
+        if keep_timing {
+            if let Some(last_timestamp) = last_timestamp {
+                sleep(Duration::from_millis(
+                    record.timestamp.saturating_sub(last_timestamp),
+                ))
+                .await;
+            }
+        }
+        last_timestamp = Some(record.timestamp);
+        debug!("Replay {:?} Record {:?}", record.kind, &record.id);
+        match record.kind {
+            RecordKind::Instruct => {
+                instruct_sender.send(InboundEntity::replayed(serde_json::from_str::<
+                    InstructEntity,
+                >(&record.payload)?))?
+            }
+            RecordKind::Manipulate => {
+                manipulate_sender.send(InboundEntity::replayed(serde_json::from_str::<
+                    ManipulateEntity,
+                >(
+                    &record.payload
+                )?))?
+            }
+            RecordKind::ModuleOperate => {
+                module_operate_sender.send(InboundEntity::replayed(serde_json::from_str::<
+                    ModuleOperate,
+                >(
+                    &record.payload
+                )?))?
+            }
+        }
+    }
+    info!("Replay {:?} Finished", file_path);
+    Ok(())
+}
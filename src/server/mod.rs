@@ -1,13 +1,32 @@
 use anyhow::Result;
 use nihility_common::{GrpcServer, NihilityServer};
+use tokio::spawn;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
 use crate::config::ServerConfig;
+use crate::entity::operation_record::InboundEntity;
 use crate::{CANCELLATION_TOKEN, INSTRUCT_SENDER, MANIPULATE_SENDER, MODULE_OPERATE_SENDER};
 
+/// 服务端收到的实体标记为非回放后转入核心处理通道
+fn relay_live<T: Send + 'static>(
+    core_sender: UnboundedSender<InboundEntity<T>>,
+) -> UnboundedSender<T> {
+    let (sender, mut receiver) = unbounded_channel::<T>();
+    spawn(async move {
+        while let Some(entity) = receiver.recv().await {
+            if core_sender.send(InboundEntity::live(entity)).is_err() {
+                break;
+            }
+        }
+    });
+    sender
+}
+
 pub async fn server_start(server_config: &ServerConfig) -> Result<()> {
-    let instruct_sender = INSTRUCT_SENDER.get().unwrap().upgrade().unwrap();
-    let manipulate_sender = MANIPULATE_SENDER.get().unwrap().upgrade().unwrap();
-    let submodule_operate_sender = MODULE_OPERATE_SENDER.get().unwrap().upgrade().unwrap();
+    let instruct_sender = relay_live(INSTRUCT_SENDER.get().unwrap().upgrade().unwrap());
+    let manipulate_sender = relay_live(MANIPULATE_SENDER.get().unwrap().upgrade().unwrap());
+    let submodule_operate_sender =
+        relay_live(MODULE_OPERATE_SENDER.get().unwrap().upgrade().unwrap());
 
     let mut grpc_server = GrpcServer::init(
         server_config.grpc_server.clone(),